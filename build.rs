@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // set by cargo, build scripts should use this directory for output files
@@ -7,19 +8,66 @@ fn main() {
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // the kernel reads its symbol table from the ramdisk to symbolize backtraces
+    let symbols = kernel_symbols(&kernel);
+    let ramdisk_path = out_dir.join("ramdisk.tar");
+    let mut ramdisk = tar::Builder::new(std::fs::File::create(&ramdisk_path).unwrap());
+    let mut header = tar::Header::new_ustar();
+    header.set_size(symbols.len() as u64);
+    header.set_mode(0o644);
+    ramdisk
+        .append_data(&mut header, "kernel.sym", symbols.as_bytes())
+        .unwrap();
+    // keymaps the kernel can switch to at runtime
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    ramdisk
+        .append_dir_all("keymaps", manifest_dir.join("keymaps"))
+        .unwrap();
+    ramdisk.finish().unwrap();
+
     // create an UEFI disk image (optional)
     let uefi_path = out_dir.join("uefi.img");
     bootloader::UefiBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&uefi_path)
         .unwrap();
 
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
     bootloader::BiosBoot::new(&kernel)
+        .set_ramdisk(&ramdisk_path)
         .create_disk_image(&bios_path)
         .unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
+    // the runner builds images with an extended ramdisk from these
+    println!("cargo:rustc-env=KERNEL_PATH={}", kernel.display());
+    println!("cargo:rustc-env=RAMDISK_PATH={}", ramdisk_path.display());
+}
+
+/// Lists the kernel's functions as sorted `<address> <size> <name>` lines
+fn kernel_symbols(kernel: &Path) -> String {
+    use object::{Object, ObjectSymbol, SymbolKind};
+
+    let data = std::fs::read(kernel).unwrap();
+    let elf = object::File::parse(&*data).unwrap();
+    let mut symbols: Vec<_> = elf
+        .symbols()
+        .filter(|sym| sym.kind() == SymbolKind::Text && sym.address() != 0)
+        .filter_map(|sym| Some((sym.address(), sym.size(), sym.name().ok()?)))
+        .collect();
+    symbols.sort_by_key(|&(addr, _, _)| addr);
+
+    let mut table = String::new();
+    for (addr, size, name) in symbols {
+        writeln!(
+            table,
+            "{addr:016x} {size:08x} {:#}",
+            rustc_demangle::demangle(name)
+        )
+        .unwrap();
+    }
+    table
 }
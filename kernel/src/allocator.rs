@@ -1,14 +1,14 @@
 #[cfg(feature = "alloc-bump")]
 pub mod bump;
-use x86_64::{
-    structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
-    },
-    VirtAddr,
-};
+use crate::memory::vma::{self, Backing, Vma, VmaError};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Address space reserved for the heap unless the boot configuration says otherwise, pages
+/// are only mapped once they're touched
+pub const HEAP_SIZE: usize = 32 * 1024 * 1024; // 32 MiB
+/// The heap has to end before the areas [`vma::reserve`] hands out
+pub const MAX_HEAP_SIZE: usize = vma::DYNAMIC_START as usize - HEAP_START;
 
 #[cfg_attr(feature = "alloc-lla", global_allocator)]
 #[cfg(feature = "alloc-lla")]
@@ -25,34 +25,27 @@ static ALLOCATOR: Locked<bump::BumpAllocator> = Locked::new(bump::BumpAllocator:
 static ALLOCATOR: good_memory_allocator::SpinLockedAllocator =
     good_memory_allocator::SpinLockedAllocator::empty();
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
-    }
+/// Sets up the heap as a demand paged [`Vma`], as large as the boot configuration says.
+/// Requires [`crate::memory::install`].
+pub fn init_heap() -> Result<(), VmaError> {
+    let heap_size = crate::config::get().heap_size;
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    vma::add(Vma {
+        name: "heap",
+        start: heap_start,
+        end: heap_start + heap_size as u64,
+        flags: PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        backing: Backing::Anonymous,
+    })?;
     unsafe {
         #[cfg(feature = "alloc-bump")]
         #[cfg(not(any(feature = "alloc-galloc", feature = "alloc-lla")))]
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START, heap_size);
         #[cfg(feature = "alloc-lla")]
         #[cfg(not(any(feature = "alloc-galloc", feature = "alloc-bump")))]
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START as *mut u8, heap_size);
         #[cfg(feature = "alloc-galloc")]
-        ALLOCATOR.init(HEAP_START, HEAP_SIZE)
+        ALLOCATOR.init(HEAP_START, heap_size)
     }
     Ok(())
 }
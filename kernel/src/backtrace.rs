@@ -0,0 +1,191 @@
+//! Stack unwinding and symbolization for panic and exception reports.
+//!
+//! The kernel is built with frame pointers (see `.cargo/config.toml`), so every frame starts
+//! with the caller's `rbp`, followed by the return address. Symbols come from `kernel.sym` on
+//! the ramdisk, which the build script generates from the kernel ELF.
+use crate::{println, serial_println};
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+/// Walks stop after this many frames, in case the frame chain is corrupted
+const MAX_FRAMES: usize = 32;
+/// Sorted lines of `<address> <size> <demangled name>`, addresses and sizes in hex
+const SYMBOL_FILE: &str = "kernel.sym";
+
+/// Set once an exception's backtrace was printed, the panic that follows it would only show
+/// the handler
+static FAULT_PRINTED: AtomicBool = AtomicBool::new(false);
+/// Difference between the link time and runtime addresses, as the kernel is relocatable
+static SLIDE: OnceCell<u64> = OnceCell::uninit();
+
+/// A function with a known name, used to figure out where the kernel was loaded
+#[inline(never)]
+fn anchor() -> u64 {
+    0x5ec7_ab1e
+}
+const ANCHOR_NAME: &str = "kernel::backtrace::anchor";
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: &'static str,
+}
+
+fn parse_symbol(line: &'static str) -> Option<Symbol> {
+    let mut parts = line.splitn(3, ' ');
+    Some(Symbol {
+        addr: u64::from_str_radix(parts.next()?, 16).ok()?,
+        size: u64::from_str_radix(parts.next()?, 16).ok()?,
+        name: parts.next()?,
+    })
+}
+
+fn symbols() -> Option<impl Iterator<Item = Symbol>> {
+    let table = crate::ramdisk::get(SYMBOL_FILE)?;
+    let table = core::str::from_utf8(table).ok()?;
+    Some(table.lines().filter_map(parse_symbol))
+}
+
+fn slide() -> Option<u64> {
+    if let Some(slide) = SLIDE.get() {
+        return Some(*slide);
+    }
+    // not cached on failure, the ramdisk might just not be set up yet
+    let link_addr = symbols()?.find(|sym| sym.name == ANCHOR_NAME)?.addr;
+    let slide = (anchor as fn() -> u64 as usize as u64).wrapping_sub(link_addr);
+    let _ = SLIDE.try_init_once(|| slide);
+    Some(slide)
+}
+
+/// Returns the function containing `addr` and the offset into it
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    let addr = addr.wrapping_sub(slide()?);
+    symbols()?
+        .take_while(|sym| sym.addr <= addr)
+        .filter(|sym| addr < sym.addr + sym.size.max(1))
+        .last()
+        .map(|sym| (sym.name, addr - sym.addr))
+}
+
+/// Return addresses of the frames starting at `rbp`, innermost first.
+///
+/// The walk stops at the first frame that isn't in mapped memory, or if that can't be
+/// checked because the page table is locked.
+pub struct Frames {
+    rbp: u64,
+    depth: usize,
+}
+
+impl Frames {
+    /// # Safety
+    /// `rbp` must be the start of a valid frame chain, e.g. the current value of the register.
+    pub unsafe fn from_rbp(rbp: u64) -> Self {
+        Self { rbp, depth: 0 }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.depth >= MAX_FRAMES
+            || self.rbp == 0
+            || !self.rbp.is_multiple_of(8)
+            || !is_mapped(self.rbp)
+            || !is_mapped(self.rbp + 8)
+        {
+            return None;
+        }
+        let frame = self.rbp as *const u64;
+        let (caller_rbp, return_addr) = unsafe { (*frame, *frame.add(1)) };
+        if return_addr == 0 {
+            return None;
+        }
+        // the stack grows down, so callers' frames must be at higher addresses
+        self.rbp = if caller_rbp > self.rbp { caller_rbp } else { 0 };
+        self.depth += 1;
+        Some(return_addr)
+    }
+}
+
+fn is_mapped(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| crate::memory::is_mapped(addr) == Some(true))
+}
+
+/// Reads the frame pointer of the calling function
+#[inline(always)]
+pub fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+    rbp
+}
+
+fn emit(args: fmt::Arguments) {
+    serial_println!("{}", args);
+    println!("{}", args);
+}
+
+fn emit_frame(index: usize, addr: u64) {
+    match resolve(addr) {
+        Some((name, offset)) => emit(format_args!(
+            "  {index:>2}: {addr:#018x} - {name}+{offset:#x}"
+        )),
+        None => emit(format_args!("  {index:>2}: {addr:#018x} - ??")),
+    }
+}
+
+/// Prints a backtrace of the caller to serial and the screen, unless it's the panic of an
+/// exception handler that already printed one with [`print_fault`]
+#[inline(always)]
+pub fn print() {
+    if !FAULT_PRINTED.load(Ordering::Relaxed) {
+        print_from(None, current_rbp());
+    }
+}
+
+/// Prints a backtrace of the code an exception interrupted, starting at the faulting
+/// instruction.
+///
+/// The handler's own frame is skipped: above its saved `rbp` lies the error code or the
+/// exception frame, not a return address.
+///
+/// # Safety
+/// `stack_frame` must be the frame the CPU pushed, as passed to an `x86-interrupt` handler,
+/// and `error_code` whether the CPU pushed one below it. The handler's prologue saves the
+/// interrupted `rbp` right below that.
+pub unsafe fn print_fault(stack_frame: &InterruptStackFrame, error_code: bool) {
+    let frame = stack_frame as *const InterruptStackFrame as *const u64;
+    let saved_rbp = frame.sub(1 + usize::from(error_code));
+    let rbp = if is_mapped(saved_rbp as u64) {
+        *saved_rbp
+    } else {
+        0
+    };
+    print_from(Some(stack_frame.instruction_pointer.as_u64()), rbp);
+    FAULT_PRINTED.store(true, Ordering::Relaxed);
+}
+
+fn print_from(instruction_pointer: Option<u64>, rbp: u64) {
+    emit(format_args!("Backtrace:"));
+    let frames = unsafe { Frames::from_rbp(rbp) };
+    for (index, addr) in instruction_pointer.into_iter().chain(frames).enumerate() {
+        emit_frame(index, addr);
+    }
+    if slide().is_none() {
+        emit(format_args!(
+            "  (no symbols, {SYMBOL_FILE} missing from the ramdisk)"
+        ));
+    }
+}
+
+#[test_case]
+fn test_parse_symbol() {
+    let sym = parse_symbol("000000000003f050 00000022 kernel::task::executor::Executor::run")
+        .expect("valid symbol line");
+    assert_eq!(sym.addr, 0x3f050);
+    assert_eq!(sym.size, 0x22);
+    assert_eq!(sym.name, "kernel::task::executor::Executor::run");
+}
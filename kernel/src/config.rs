@@ -0,0 +1,156 @@
+//! Boot configuration, read from the `cmdline` file on the ramdisk.
+//!
+//! The file holds options like a Linux kernel command line, separated by whitespace or
+//! newlines, with `#` starting a comment:
+//!
+//! ```text
+//! heap=64M keymap=de
+//! init=/bin/shell.wasm loglevel=debug
+//! ```
+//!
+//! It is parsed without allocating, so [`get`] works before the heap exists. Everything not
+//! given keeps its default.
+use crate::allocator;
+use conquer_once::spin::OnceCell;
+use log::LevelFilter;
+
+/// Path of the command line on the ramdisk
+pub const CMDLINE_PATH: &str = "cmdline";
+/// The heap is never made smaller than this
+const MIN_HEAP_SIZE: usize = 1024 * 1024;
+
+static CONFIG: OnceCell<BootConfig> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig {
+    /// `heap=<size>`: address space for the heap, with an optional `K`, `M` or `G` suffix
+    pub heap_size: usize,
+    /// `keymap=<name>`: keyboard layout or keymap, see [`crate::keyboard::select`]
+    pub keymap: Option<&'static str>,
+    /// `init=<path.wasm>`: the program to run from the ramdisk, unless there's a
+    /// [service manifest](crate::service)
+    pub init: Option<&'static str>,
+    /// `loglevel=<level>`: `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: LevelFilter,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            heap_size: allocator::HEAP_SIZE,
+            keymap: None,
+            init: None,
+            log_level: LevelFilter::Info,
+        }
+    }
+}
+
+impl BootConfig {
+    /// Parses a command line, warning about and skipping options it doesn't understand
+    pub fn parse(cmdline: &'static str) -> BootConfig {
+        let mut config = BootConfig::default();
+        let options = cmdline
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace);
+        for option in options {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            let valid = match key {
+                "heap" => parse_size(value)
+                    .map(|size| config.heap_size = heap_size(size))
+                    .is_some(),
+                "keymap" => {
+                    config.keymap = Some(value).filter(|name| !name.is_empty());
+                    config.keymap.is_some()
+                }
+                "init" => {
+                    config.init = Some(value).filter(|path| !path.is_empty());
+                    config.init.is_some()
+                }
+                "loglevel" => value.parse().map(|level| config.log_level = level).is_ok(),
+                _ => {
+                    log::warn!("unknown boot option {option:?}");
+                    continue;
+                }
+            };
+            if !valid {
+                log::warn!("bad boot option {option:?}");
+            }
+        }
+        config
+    }
+}
+
+/// Reads the command line from the ramdisk, which must be initialized already, and applies
+/// the log level. Without a command line the defaults are used.
+pub fn init() -> &'static BootConfig {
+    let config = CONFIG.get_or_init(|| {
+        let cmdline = crate::ramdisk::get(CMDLINE_PATH)
+            .and_then(|data| core::str::from_utf8(data).ok())
+            .unwrap_or_default();
+        log::info!("command line: {}", cmdline.trim());
+        BootConfig::parse(cmdline)
+    });
+    crate::logger::set_level(config.log_level);
+    config
+}
+
+/// The boot configuration, the defaults until [`init`] ran
+pub fn get() -> BootConfig {
+    CONFIG.get().copied().unwrap_or_default()
+}
+
+/// Rounds a requested heap size to whole pages within what the address space allows
+fn heap_size(size: usize) -> usize {
+    if size > allocator::MAX_HEAP_SIZE {
+        log::warn!(
+            "heap size {size} is too large, using {}",
+            allocator::MAX_HEAP_SIZE
+        );
+        return allocator::MAX_HEAP_SIZE;
+    }
+    size.max(MIN_HEAP_SIZE).next_multiple_of(4096)
+}
+
+/// Parses a size like `4096`, `512K` or `2M`
+fn parse_size(value: &str) -> Option<usize> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+#[test_case]
+fn test_parse() {
+    let config = BootConfig::parse("heap=64M keymap=de\n# the first program\ninit=/bin/a.wasm");
+    assert_eq!(config.heap_size, 64 * 1024 * 1024);
+    assert_eq!(config.keymap, Some("de"));
+    assert_eq!(config.init, Some("/bin/a.wasm"));
+    assert_eq!(config.log_level, LevelFilter::Info);
+}
+
+#[test_case]
+fn test_parse_bad_options() {
+    let config = BootConfig::parse("heap=lots init= loglevel=loud quiet heap=1K");
+    // the last heap size is too small, but valid
+    assert_eq!(config.heap_size, MIN_HEAP_SIZE);
+    assert_eq!(config.init, None);
+    assert_eq!(config.log_level, LevelFilter::Info);
+    assert_eq!(parse_size("2G"), Some(2 << 30));
+    assert_eq!(parse_size("M"), None);
+}
+
+#[test_case]
+fn test_parse_huge_heap() {
+    let config = BootConfig::parse("heap=10000000G");
+    assert_eq!(config.heap_size, allocator::MAX_HEAP_SIZE);
+    let config = BootConfig::parse("heap=30000G");
+    assert_eq!(config.heap_size, allocator::MAX_HEAP_SIZE);
+    assert_eq!(
+        allocator::HEAP_START + allocator::MAX_HEAP_SIZE,
+        0x_6000_0000_0000
+    );
+}
@@ -0,0 +1,39 @@
+//! What happens when the CPU raises an exception.
+//!
+//! - page faults inside a [virtual memory area](crate::memory::vma) are resolved by mapping
+//!   the page, and the faulting instruction is retried
+//! - everything else is a kernel bug, and causes a panic with the fault's details
+use core::fmt;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Prints exception names like `GENERAL_PROTECTION_FAULT` with spaces
+pub struct FaultName<'a>(pub &'a str);
+
+impl fmt::Display for FaultName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use fmt::Write;
+        for c in self.0.chars() {
+            f.write_char(if c == '_' { ' ' } else { c })?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the fault policy to an exception that can't be resolved. WASM programs run in an
+/// interpreter, so even a fault while polling a process is a kernel bug.
+pub(crate) fn handle(
+    name: FaultName,
+    error_code: Option<u64>,
+    stack_frame: &InterruptStackFrame,
+) -> ! {
+    let location = crate::backtrace::resolve(stack_frame.instruction_pointer.as_u64());
+    // the stack frame was passed on from the handler, so it's still the one the CPU pushed
+    unsafe { crate::backtrace::print_fault(stack_frame, error_code.is_some()) };
+    panic!(
+        "EXCEPTION: {} (error code {:?}) at {}:\n{:#?}",
+        name,
+        error_code,
+        location.map_or("??", |(symbol, _)| symbol),
+        stack_frame
+    );
+}
@@ -1,13 +1,31 @@
-use alloc::boxed::Box;
-use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
+//! Text console on the framebuffer provided by the bootloader
+mod ansi;
+mod color;
+mod damage;
+mod grid;
+mod pointer;
+
+pub use color::Color;
+pub use damage::Rect;
+pub use grid::{Attributes, Cell, TermColor};
+
+use crate::graphics::{Bitmap, Canvas};
+use crate::memory::vma::{self, VmaError};
+use ansi::{Action, Csi, Parser};
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
 use conquer_once::spin::OnceCell;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::{fmt, ptr};
+use damage::Damage;
 use font_constants::BACKUP_CHAR;
-use lazy_static::lazy_static;
+use grid::Grid;
 use noto_sans_mono_bitmap::{
     get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
 };
+use pointer::Pointer;
 use spinning_top::Spinlock;
+use x86_64::structures::paging::PageTableFlags;
 
 pub static FRAMEBUFFER: OnceCell<Spinlock<FrameBufferWriter>> = OnceCell::uninit();
 
@@ -28,6 +46,22 @@ macro_rules! println {
         concat!($fmt, "\n"), $($arg)*));
 }
 
+/// Prints to framebuffer in the given [`Color`](crate::framebuffer::Color).
+#[macro_export]
+macro_rules! color_print {
+    ($color:expr, $($arg:tt)*) => {
+        $crate::framebuffer::_color_print($color, format_args!($($arg)*))
+    };
+}
+
+/// Prints to framebuffer in the given [`Color`](crate::framebuffer::Color), appending a newline.
+#[macro_export]
+macro_rules! color_println {
+    ($color:expr, $fmt:expr) => ($crate::color_print!($color, concat!($fmt, "\n")));
+    ($color:expr, $fmt:expr, $($arg:tt)*) => ($crate::color_print!(
+        $color, concat!($fmt, "\n"), $($arg)*));
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use fmt::Write;
@@ -39,6 +73,16 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+#[doc(hidden)]
+pub fn _color_print(color: Color, args: fmt::Arguments) {
+    use x86_64::instructions::interrupts;
+    interrupts::without_interrupts(|| {
+        if let Some(fb) = FRAMEBUFFER.get() {
+            fb.lock().write_colored(color, args).unwrap()
+        }
+    });
+}
+
 /// Additional vertical space between lines
 const LINE_SPACING: usize = 2;
 /// Additional horizontal space between characters.
@@ -66,116 +110,742 @@ mod font_constants {
 }
 
 /// Returns the raster of the given char or the raster of [`font_constants::BACKUP_CHAR`].
-fn get_char_raster(c: char) -> RasterizedChar {
-    fn get(c: char) -> Option<RasterizedChar> {
-        get_raster(
-            c,
-            font_constants::FONT_WEIGHT,
-            font_constants::CHAR_RASTER_HEIGHT,
-        )
+fn get_char_raster(c: char, weight: FontWeight) -> RasterizedChar {
+    fn get(c: char, weight: FontWeight) -> Option<RasterizedChar> {
+        get_raster(c, weight, font_constants::CHAR_RASTER_HEIGHT)
+    }
+    get(c, weight)
+        .unwrap_or_else(|| get(BACKUP_CHAR, weight).expect("Should get raster of backup char."))
+}
+
+/// Size of a console cell in pixels
+const CELL_WIDTH: usize = font_constants::CHAR_RASTER_WIDTH + LETTER_SPACING;
+const CELL_HEIGHT: usize = font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
+
+/// Columns between tab stops
+const TAB_WIDTH: usize = 8;
+
+/// Upper bounds of the console size. The cells are a static, so the console works before the
+/// heap does.
+const MAX_COLUMNS: usize = 320;
+const MAX_ROWS: usize = 128;
+
+static mut CELLS: [Cell; MAX_COLUMNS * MAX_ROWS] = [Cell::BLANK; MAX_COLUMNS * MAX_ROWS];
+static CELLS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// How long the cursor stays on or off
+const CURSOR_BLINK_MS: u64 = 500;
+
+/// Rate at which [`compositor`] copies changes to the screen
+const FRAME_RATE: u64 = 50;
+
+/// Moves drawing to a back buffer in RAM, which [`flush`] copies to the screen.
+///
+/// Needs [`crate::memory::install`]. Until this is called, everything is drawn straight to
+/// video memory.
+pub fn enable_back_buffer() -> Result<(), VmaError> {
+    let Some(fb) = FRAMEBUFFER.get() else {
+        return Ok(());
+    };
+    let len = fb.lock().framebuffer.len();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let start = vma::reserve("framebuffer back buffer", len as u64, flags)?;
+    // mapped up front, drawing happens with interrupts disabled
+    vma::populate(start)?;
+    let back_buffer = unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr::<u8>(), len) };
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut fb = fb.lock();
+        back_buffer.copy_from_slice(fb.framebuffer);
+        fb.back_buffer = Some(back_buffer);
+    });
+    Ok(())
+}
+
+/// Copies whatever changed in the back buffer to the screen
+pub fn flush() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if let Some(fb) = FRAMEBUFFER.get() {
+            fb.lock().flush();
+        }
+    });
+}
+
+/// Flushes the back buffer at [`FRAME_RATE`], meant to be spawned as a task
+pub async fn compositor() {
+    loop {
+        crate::time::sleep_ticks(crate::time::TICK_HZ / FRAME_RATE).await;
+        flush();
     }
-    get(c).unwrap_or_else(|| get(BACKUP_CHAR).expect("Should get raster of backup char."))
 }
 
-/// Allows logging text to a pixel-based framebuffer.
+/// Blinks the console cursor, meant to be spawned as a task
+pub async fn blink_cursor() {
+    use x86_64::instructions::interrupts;
+    loop {
+        crate::time::sleep_ms(CURSOR_BLINK_MS).await;
+        interrupts::without_interrupts(|| {
+            if let Some(fb) = FRAMEBUFFER.get() {
+                fb.lock().toggle_cursor();
+            }
+        });
+    }
+}
+
+/// A text console on a pixel-based framebuffer.
+///
+/// Text goes into a grid of [`Cell`]s, which is rendered to the framebuffer as it changes.
+/// Understands the usual control characters and the ANSI escape sequences for cursor movement,
+/// erasing, scrolling and SGR attributes, including 256 colour and true colour ones.
+///
+/// Once [`enable_back_buffer`] was called, drawing goes to a back buffer and the changed parts
+/// are only copied to the screen by [`FrameBufferWriter::flush`], which also draws the mouse
+/// pointer on top.
 pub struct FrameBufferWriter {
+    /// Video memory
     framebuffer: &'static mut [u8],
+    back_buffer: Option<&'static mut [u8]>,
+    /// Parts of the back buffer not flushed yet
+    damage: Damage,
+    /// Only drawn on the screen, never into the back buffer
+    pointer: Pointer,
     info: FrameBufferInfo,
-    x_pos: usize,
-    y_pos: usize,
+    /// Colours of [`TermColor::Default`]
+    default_fg: Color,
+    default_bg: Color,
+    grid: Grid,
+    parser: Parser,
+    /// Cursor position, in cells
+    column: usize,
+    row: usize,
+    /// Set after printing into the last column, the next printed char goes to a new line
+    wrap_pending: bool,
+    /// Attributes of newly printed chars
+    attrs: Attributes,
+    /// Saved by `ESC 7` or `ESC [ s`
+    saved: (usize, usize, Attributes),
+    /// Shown or hidden with `ESC [ ? 25 h` and `ESC [ ? 25 l`
+    cursor_enabled: bool,
+    /// Whether the cursor is drawn right now, flips while blinking
+    cursor_drawn: bool,
 }
 
 impl FrameBufferWriter {
-    /// Creates a new logger that uses the given framebuffer.
+    /// Creates a new console that uses the given framebuffer.
+    ///
+    /// There can only be one, as they share the cell storage.
     pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
-        let mut logger = Self {
+        assert!(
+            !CELLS_TAKEN.swap(true, Ordering::AcqRel),
+            "there can only be one framebuffer console"
+        );
+        let cells = unsafe { &mut *ptr::addr_of_mut!(CELLS) };
+        let columns = ((info.width - 2 * BORDER_PADDING) / CELL_WIDTH).min(MAX_COLUMNS);
+        let rows = ((info.height - 2 * BORDER_PADDING) / CELL_HEIGHT).min(MAX_ROWS);
+        let mut console = Self {
             framebuffer,
+            back_buffer: None,
+            damage: Damage::new(),
+            pointer: Pointer::new(),
             info,
-            x_pos: 0,
-            y_pos: 0,
+            default_fg: Color::LIGHT_GRAY,
+            default_bg: Color::BLACK,
+            grid: Grid::new(cells, columns, rows),
+            parser: Parser::new(),
+            column: 0,
+            row: 0,
+            wrap_pending: false,
+            attrs: Attributes::DEFAULT,
+            saved: (0, 0, Attributes::DEFAULT),
+            cursor_enabled: true,
+            cursor_drawn: false,
+        };
+        console.clear();
+        console
+    }
+
+    /// Erases all text on the screen and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        self.grid.clear(Cell::erased(self.attrs));
+        self.column = 0;
+        self.row = 0;
+        self.wrap_pending = false;
+        self.cursor_drawn = false;
+        // the border isn't part of any cell
+        for y in 0..self.info.height {
+            for x in 0..self.info.width {
+                self.put_pixel(x, y, self.default_bg);
+            }
+        }
+        self.damage(Rect::new(0, 0, self.info.width, self.info.height));
+        self.render_rows(0..self.grid.rows());
+    }
+
+    /// Changes the colours used where no SGR colour is set, and redraws the screen
+    pub fn set_default_colors(&mut self, fg: Color, bg: Color) {
+        self.default_fg = fg;
+        self.default_bg = bg;
+        self.render_rows(0..self.grid.rows());
+    }
+
+    /// Writes text with the given foreground colour, escape sequences in it can still change it
+    pub fn write_colored(&mut self, color: Color, args: fmt::Arguments) -> fmt::Result {
+        use fmt::Write;
+        let attrs = self.attrs;
+        self.attrs.fg = TermColor::Rgb(color);
+        let result = self.write_fmt(args);
+        self.attrs = attrs;
+        result
+    }
+
+    /// Size of the framebuffer in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.info.width, self.info.height)
+    }
+
+    /// Copies the damaged parts of the back buffer to video memory, and draws the mouse pointer
+    /// over them
+    pub fn flush(&mut self) {
+        if self.back_buffer.is_none() || (self.damage.is_empty() && !self.pointer.dirty) {
+            return;
+        }
+        let pointer = match self.pointer.visible {
+            true => self.pointer.rect().intersection(&self.screen()),
+            false => Rect::default(),
         };
-        logger.clear();
-        logger
+        // redrawn if it moved or anything under it was overwritten
+        let mut redraw_pointer = self.pointer.dirty;
+        let back_buffer = self.back_buffer.as_deref().unwrap();
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        for rect in self.damage.take() {
+            for y in rect.y..rect.bottom() {
+                let start = (y * self.info.stride + rect.x) * bytes_per_pixel;
+                let end = start + rect.width * bytes_per_pixel;
+                self.framebuffer[start..end].copy_from_slice(&back_buffer[start..end]);
+            }
+            redraw_pointer |= !rect.intersection(&pointer).is_empty();
+        }
+        if redraw_pointer && !pointer.is_empty() {
+            self.draw_pointer();
+        }
+        self.pointer.dirty = false;
+    }
+
+    /// Blends the pointer sprite over the back buffer onto the screen
+    fn draw_pointer(&mut self) {
+        let origin = self.pointer.rect();
+        let visible = origin.intersection(&self.screen());
+        let format = self.info.pixel_format;
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        for y in visible.y..visible.bottom() {
+            for x in visible.x..visible.right() {
+                let src = self.pointer.sprite().get(x - origin.x, y - origin.y);
+                let color = match src.a {
+                    0 => continue,
+                    255 => src.color(),
+                    alpha => self.pixel(x, y).blend(src.color(), alpha),
+                };
+                let Some(bytes) = encode(format, color) else {
+                    return;
+                };
+                let byte_offset = (y * self.info.stride + x) * bytes_per_pixel;
+                self.framebuffer[byte_offset..][..bytes_per_pixel]
+                    .copy_from_slice(&bytes[..bytes_per_pixel]);
+            }
+        }
     }
 
-    fn newline(&mut self) {
-        self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
-        self.carriage_return()
+    /// Moves the mouse pointer, `x` and `y` being where its hotspot points to.
+    ///
+    /// The pointer is only shown with a back buffer, and updated on the next flush.
+    pub fn move_pointer(&mut self, x: usize, y: usize) {
+        if (x, y) == (self.pointer.x, self.pointer.y) {
+            return;
+        }
+        self.hide_pointer();
+        self.pointer.x = x;
+        self.pointer.y = y;
     }
 
-    fn carriage_return(&mut self) {
-        self.x_pos = BORDER_PADDING;
+    pub fn set_pointer_visible(&mut self, visible: bool) {
+        self.hide_pointer();
+        self.pointer.visible = visible;
     }
 
-    /// Erases all text on the screen. Resets `self.x_pos` and `self.y_pos`.
-    pub fn clear(&mut self) {
-        self.x_pos = BORDER_PADDING;
-        self.y_pos = BORDER_PADDING;
-        self.framebuffer.fill(0);
+    /// Replaces the arrow with another image, `hotspot` being the pixel of it that points
+    pub fn set_pointer_sprite(&mut self, sprite: Bitmap, hotspot: (usize, usize)) {
+        self.hide_pointer();
+        self.pointer.set_sprite(sprite, hotspot);
     }
 
-    fn width(&self) -> usize {
-        self.info.width
+    /// Flushes the area under the pointer again, which restores what was drawn there
+    fn hide_pointer(&mut self) {
+        if self.pointer.visible {
+            let rect = self.pointer.rect();
+            self.damage(rect);
+        }
+        self.pointer.dirty = true;
     }
 
-    fn height(&self) -> usize {
-        self.info.height
+    /// Marks an area as changed, clipped to the screen
+    pub fn damage(&mut self, rect: Rect) {
+        if self.back_buffer.is_some() {
+            let screen = self.screen();
+            self.damage.add(rect.intersection(&screen));
+        }
     }
 
-    pub fn size(&self) -> (usize, usize) {
-        (self.info.width, self.info.height)
+    fn screen(&self) -> Rect {
+        Rect::new(0, 0, self.info.width, self.info.height)
+    }
+
+    /// Where drawing goes, the back buffer if there is one
+    fn buffer(&mut self) -> &mut [u8] {
+        match self.back_buffer {
+            Some(ref mut back_buffer) => back_buffer,
+            None => self.framebuffer,
+        }
+    }
+
+    fn buffer_ref(&self) -> &[u8] {
+        self.back_buffer.as_deref().unwrap_or(self.framebuffer)
+    }
+
+    /// Size of the console in columns and rows
+    pub fn text_size(&self) -> (usize, usize) {
+        (self.grid.columns(), self.grid.rows())
+    }
+
+    /// Shows or hides the cursor, for blinking
+    pub fn toggle_cursor(&mut self) {
+        if self.cursor_drawn {
+            self.hide_cursor();
+        } else {
+            self.show_cursor();
+        }
     }
 
-    /// Writes a single char to the framebuffer. Takes care of special control characters, such as
-    /// newlines and carriage returns.
+    fn show_cursor(&mut self) {
+        if self.cursor_enabled && !self.cursor_drawn {
+            self.cursor_drawn = true;
+            self.render_cell(self.column, self.row);
+        }
+    }
+
+    fn hide_cursor(&mut self) {
+        if self.cursor_drawn {
+            self.cursor_drawn = false;
+            self.render_cell(self.column, self.row);
+        }
+    }
+
+    /// Feeds a single char through the escape sequence parser and handles the result.
     fn write_char(&mut self, c: char) {
+        match self.parser.advance(c) {
+            Some(Action::Print(c)) => self.print_char(c),
+            Some(Action::Control(c)) => self.control(c),
+            Some(Action::Escape(c)) => self.escape(c),
+            Some(Action::Csi(csi)) => self.csi(&csi),
+            None => {}
+        }
+    }
+
+    fn print_char(&mut self, c: char) {
+        if self.wrap_pending {
+            self.wrap_pending = false;
+            self.column = 0;
+            self.line_feed();
+        }
+        self.grid.set(
+            self.column,
+            self.row,
+            Cell {
+                c,
+                attrs: self.attrs,
+            },
+        );
+        self.render_cell(self.column, self.row);
+        if self.column + 1 < self.grid.columns() {
+            self.column += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn control(&mut self, c: char) {
+        match c {
+            // kernel output only ever uses `\n`, so it returns the carriage as well
+            '\n' | '\x0b' | '\x0c' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            '\r' => self.move_to(0, self.row),
+            '\x08' => self.move_to(self.column.saturating_sub(1), self.row),
+            '\t' => self.move_to((self.column / TAB_WIDTH + 1) * TAB_WIDTH, self.row),
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, c: char) {
         match c {
-            '\n' => self.newline(),
-            '\r' => self.carriage_return(),
-            c => {
-                let new_xpos = self.x_pos + font_constants::CHAR_RASTER_WIDTH;
-                if new_xpos >= self.width() {
-                    self.newline();
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            // index, next line and reverse index
+            'D' => self.line_feed(),
+            'E' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            'M' => {
+                if self.row == 0 {
+                    self.scroll_down(1);
+                } else {
+                    self.move_to(self.column, self.row - 1);
                 }
-                let new_ypos =
-                    self.y_pos + font_constants::CHAR_RASTER_HEIGHT.val() + BORDER_PADDING;
-                if new_ypos >= self.height() {
-                    self.clear();
+            }
+            'c' => {
+                self.attrs = Attributes::DEFAULT;
+                self.cursor_enabled = true;
+                self.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        let n = usize::from(csi.param(0, 1));
+        let (column, row) = (self.column, self.row);
+        match (csi.private, csi.action) {
+            (false, 'A') => self.move_to(column, row.saturating_sub(n)),
+            (false, 'B') => self.move_to(column, row + n),
+            (false, 'C') => self.move_to(column + n, row),
+            (false, 'D') => self.move_to(column.saturating_sub(n), row),
+            (false, 'E') => self.move_to(0, row + n),
+            (false, 'F') => self.move_to(0, row.saturating_sub(n)),
+            (false, 'G') => self.move_to(n - 1, row),
+            (false, 'd') => self.move_to(column, n - 1),
+            (false, 'H' | 'f') => self.move_to(usize::from(csi.param(1, 1)) - 1, n - 1),
+            (false, 'J') => self.erase_display(csi.param(0, 0)),
+            (false, 'K') => self.erase_line(csi.param(0, 0)),
+            (false, 'X') => self.erase_cells(row, column..column + n),
+            (false, '@') => self.shift_right(n),
+            (false, 'P') => self.shift_left(n),
+            (false, 'S') => self.scroll_up(n),
+            (false, 'T') => self.scroll_down(n),
+            (false, 'm') => self.sgr(csi.params()),
+            (false, 's') => self.save_cursor(),
+            (false, 'u') => self.restore_cursor(),
+            (true, 'h' | 'l') if csi.params().contains(&25) => {
+                self.cursor_enabled = csi.action == 'h';
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies "select graphic rendition" parameters
+    fn sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.attrs = Attributes::DEFAULT;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            let attrs = &mut self.attrs;
+            match param {
+                0 => *attrs = Attributes::DEFAULT,
+                1 => attrs.bold = true,
+                22 => attrs.bold = false,
+                4 => attrs.underline = true,
+                24 => attrs.underline = false,
+                7 => attrs.reverse = true,
+                27 => attrs.reverse = false,
+                30..=37 => attrs.fg = TermColor::Indexed((param - 30) as u8),
+                90..=97 => attrs.fg = TermColor::Indexed((param - 90 + 8) as u8),
+                39 => attrs.fg = TermColor::Default,
+                40..=47 => attrs.bg = TermColor::Indexed((param - 40) as u8),
+                100..=107 => attrs.bg = TermColor::Indexed((param - 100 + 8) as u8),
+                49 => attrs.bg = TermColor::Default,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(|i| TermColor::Indexed(i as u8)),
+                        Some(2) => {
+                            let mut channel = || params.next().unwrap_or(0) as u8;
+                            Some(TermColor::Rgb(Color::rgb(channel(), channel(), channel())))
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if param == 38 {
+                            attrs.fg = color;
+                        } else {
+                            attrs.bg = color;
+                        }
+                    }
                 }
-                self.write_rendered_char(get_char_raster(c));
+                _ => {}
             }
         }
     }
 
-    /// Prints a rendered char into the framebuffer.
-    /// Updates `self.x_pos`.
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                self.write_pixel(self.x_pos + x, self.y_pos + y, *byte);
+    fn save_cursor(&mut self) {
+        self.saved = (self.column, self.row, self.attrs);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (column, row, attrs) = self.saved;
+        self.attrs = attrs;
+        self.move_to(column, row);
+    }
+
+    /// Moves the cursor, clamped to the grid
+    fn move_to(&mut self, column: usize, row: usize) {
+        self.column = column.min(self.grid.columns() - 1);
+        self.row = row.min(self.grid.rows() - 1);
+        self.wrap_pending = false;
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.grid.rows() {
+            self.row += 1;
+        } else {
+            self.scroll_up(1);
+        }
+    }
+
+    /// `ESC [ n J`: 0 erases below the cursor, 1 above it, 2 and 3 everything
+    fn erase_display(&mut self, mode: u16) {
+        let (columns, rows) = (self.grid.columns(), self.grid.rows());
+        match mode {
+            0 => {
+                self.erase_cells(self.row, self.column..columns);
+                for row in self.row + 1..rows {
+                    self.erase_cells(row, 0..columns);
+                }
+            }
+            1 => {
+                for row in 0..self.row {
+                    self.erase_cells(row, 0..columns);
+                }
+                self.erase_cells(self.row, 0..self.column + 1);
             }
+            2 | 3 => {
+                for row in 0..rows {
+                    self.erase_cells(row, 0..columns);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `ESC [ n K`: 0 erases right of the cursor, 1 left of it, 2 the whole line
+    fn erase_line(&mut self, mode: u16) {
+        let columns = self.grid.columns();
+        match mode {
+            0 => self.erase_cells(self.row, self.column..columns),
+            1 => self.erase_cells(self.row, 0..self.column + 1),
+            2 => self.erase_cells(self.row, 0..columns),
+            _ => {}
+        }
+    }
+
+    fn erase_cells(&mut self, row: usize, columns: Range<usize>) {
+        let columns = columns.start..columns.end.min(self.grid.columns());
+        let blank = Cell::erased(self.attrs);
+        self.grid.row_mut(row)[columns.clone()].fill(blank);
+        for column in columns {
+            self.render_cell(column, row);
         }
-        self.x_pos += rendered_char.width() + LETTER_SPACING;
     }
 
-    pub fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
-        let pixel_offset = y * self.info.stride + x;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [intensity, intensity, intensity / 2, 0],
-            PixelFormat::Bgr => [intensity / 2, intensity, intensity, 0],
-            PixelFormat::U8 => [if intensity > 200 { 0xf } else { 0 }, 0, 0, 0],
-            other => {
-                // set a supported (but invalid) pixel format before panicking to avoid a double
-                // panic; it might not be readable though
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in logger", other)
+    /// Inserts `n` blank cells at the cursor, moving the rest of the line right
+    fn shift_right(&mut self, n: usize) {
+        let (column, blank) = (self.column, Cell::erased(self.attrs));
+        let line = &mut self.grid.row_mut(self.row)[column..];
+        let n = n.min(line.len());
+        line.copy_within(..line.len() - n, n);
+        line[..n].fill(blank);
+        self.render_row(self.row, column..self.grid.columns());
+    }
+
+    /// Deletes `n` cells at the cursor, moving the rest of the line left
+    fn shift_left(&mut self, n: usize) {
+        let (column, blank) = (self.column, Cell::erased(self.attrs));
+        let line = &mut self.grid.row_mut(self.row)[column..];
+        let n = n.min(line.len());
+        line.copy_within(n.., 0);
+        let len = line.len();
+        line[len - n..].fill(blank);
+        self.render_row(self.row, column..self.grid.columns());
+    }
+
+    /// Scrolls the text up by `n` rows, moving the pixels instead of redrawing them
+    fn scroll_up(&mut self, n: usize) {
+        let rows = self.grid.rows();
+        let n = n.min(rows);
+        self.grid.scroll_up(n, Cell::erased(self.attrs));
+        let (start, row_bytes) = self.text_bytes();
+        self.buffer()
+            .copy_within(start + n * row_bytes..start + rows * row_bytes, start);
+        self.damage(self.text_area());
+        self.render_rows(rows - n..rows);
+    }
+
+    /// Scrolls the text down by `n` rows, the opposite of [`Self::scroll_up`]
+    fn scroll_down(&mut self, n: usize) {
+        let rows = self.grid.rows();
+        let n = n.min(rows);
+        self.grid.scroll_down(n, Cell::erased(self.attrs));
+        let (start, row_bytes) = self.text_bytes();
+        self.buffer()
+            .copy_within(start..start + (rows - n) * row_bytes, start + n * row_bytes);
+        self.damage(self.text_area());
+        self.render_rows(0..n);
+    }
+
+    /// The pixels covered by cells
+    fn text_area(&self) -> Rect {
+        Rect::new(
+            BORDER_PADDING,
+            BORDER_PADDING,
+            self.grid.columns() * CELL_WIDTH,
+            self.grid.rows() * CELL_HEIGHT,
+        )
+    }
+
+    /// Byte offset of the first text row in the framebuffer, and the size of a text row
+    fn text_bytes(&self) -> (usize, usize) {
+        let line_bytes = self.info.stride * self.info.bytes_per_pixel;
+        (BORDER_PADDING * line_bytes, CELL_HEIGHT * line_bytes)
+    }
+
+    fn render_rows(&mut self, rows: Range<usize>) {
+        for row in rows {
+            self.render_row(row, 0..self.grid.columns());
+        }
+    }
+
+    fn render_row(&mut self, row: usize, columns: Range<usize>) {
+        for column in columns {
+            self.render_cell(column, row);
+        }
+    }
+
+    /// Draws a cell, including the spacing around the glyph.
+    ///
+    /// Glyph rasters are coverage values, so they're alpha blended onto the background.
+    fn render_cell(&mut self, column: usize, row: usize) {
+        let cell = self.grid.get(column, row);
+        let cursor = self.cursor_drawn && (column, row) == (self.column, self.row);
+        let mut fg = cell.attrs.fg.resolve(self.default_fg);
+        let mut bg = cell.attrs.bg.resolve(self.default_bg);
+        if cell.attrs.reverse != cursor {
+            core::mem::swap(&mut fg, &mut bg);
+        }
+        let weight = match cell.attrs.bold {
+            true => FontWeight::Bold,
+            false => font_constants::FONT_WEIGHT,
+        };
+        let raster = get_char_raster(cell.c, weight);
+        let underline = cell.attrs.underline.then(|| raster.height() - 1);
+
+        let x0 = BORDER_PADDING + column * CELL_WIDTH;
+        let y0 = BORDER_PADDING + row * CELL_HEIGHT;
+        for y in 0..CELL_HEIGHT {
+            let raster_row = raster.raster().get(y);
+            for x in 0..CELL_WIDTH {
+                let alpha = match raster_row.and_then(|r| r.get(x)) {
+                    Some(_) if underline == Some(y) => 255,
+                    Some(&alpha) => alpha,
+                    None => 0,
+                };
+                self.put_pixel(x0 + x, y0 + y, bg.blend(fg, alpha));
             }
+        }
+        self.damage(Rect::new(x0, y0, CELL_WIDTH, CELL_HEIGHT));
+    }
+
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.put_pixel(x, y, color);
+        self.damage(Rect::new(x, y, 1, 1));
+    }
+
+    /// Draws a pixel without marking it as damaged
+    fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let Some(color) = encode(self.info.pixel_format, color) else {
+            // set a supported (but invalid) pixel format before panicking to avoid a double
+            // panic; it might not be readable though
+            let format = self.info.pixel_format;
+            self.info.pixel_format = PixelFormat::Rgb;
+            panic!("pixel format {:?} not supported in logger", format)
         };
         let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = pixel_offset * bytes_per_pixel;
-        self.framebuffer[byte_offset..(byte_offset + bytes_per_pixel)]
+        let byte_offset = (y * self.info.stride + x) * bytes_per_pixel;
+        self.buffer()[byte_offset..(byte_offset + bytes_per_pixel)]
             .copy_from_slice(&color[..bytes_per_pixel]);
-        let _ = unsafe { ptr::read_volatile(&self.framebuffer[byte_offset]) };
+    }
+}
+
+/// The bytes of a pixel in the given format, `None` if it isn't supported
+fn encode(format: PixelFormat, color: Color) -> Option<[u8; 4]> {
+    let Color { r, g, b } = color;
+    Some(match format {
+        PixelFormat::Rgb => [r, g, b, 0],
+        PixelFormat::Bgr => [b, g, r, 0],
+        PixelFormat::U8 => [color.luma(), 0, 0, 0],
+        // positions are bit offsets into the little endian pixel
+        PixelFormat::Unknown {
+            red_position,
+            green_position,
+            blue_position,
+        } => (u32::from(r) << red_position
+            | u32::from(g) << green_position
+            | u32::from(b) << blue_position)
+            .to_le_bytes(),
+        _ => return None,
+    })
+}
+
+/// Reads back a pixel written by [`encode`]
+fn decode(format: PixelFormat, bytes: [u8; 4]) -> Color {
+    let [b0, b1, b2, _] = bytes;
+    match format {
+        PixelFormat::Rgb => Color::rgb(b0, b1, b2),
+        PixelFormat::Bgr => Color::rgb(b2, b1, b0),
+        PixelFormat::Unknown {
+            red_position,
+            green_position,
+            blue_position,
+        } => {
+            let pixel = u32::from_le_bytes(bytes);
+            let channel = |position: u8| (pixel >> position) as u8;
+            Color::rgb(
+                channel(red_position),
+                channel(green_position),
+                channel(blue_position),
+            )
+        }
+        _ => Color::rgb(b0, b0, b0),
+    }
+}
+
+impl Canvas for FrameBufferWriter {
+    fn size(&self) -> (usize, usize) {
+        self.size()
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.put_pixel(x, y, color);
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let byte_offset = (y * self.info.stride + x) * bytes_per_pixel;
+        let mut bytes = [0; 4];
+        bytes[..bytes_per_pixel.min(4)]
+            .copy_from_slice(&self.buffer_ref()[byte_offset..][..bytes_per_pixel.min(4)]);
+        decode(self.info.pixel_format, bytes)
+    }
+
+    fn damage(&mut self, rect: Rect) {
+        self.damage(rect);
     }
 }
 
@@ -184,9 +854,11 @@ unsafe impl Sync for FrameBufferWriter {}
 
 impl fmt::Write for FrameBufferWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.hide_cursor();
         for c in s.chars() {
             self.write_char(c);
         }
+        self.show_cursor();
         Ok(())
     }
 }
@@ -0,0 +1,195 @@
+//! Parser for the ANSI/VT100 escape sequences understood by the console.
+//!
+//! Only splits the input into [`Action`]s, interpreting them is up to the console.
+
+/// Maximum number of parameters of a control sequence, further ones are dropped
+pub const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// A character to print
+    Print(char),
+    /// A C0 control character, like `\n` or backspace
+    Control(char),
+    /// An escape sequence `ESC <final>`, like `ESC 7`
+    Escape(char),
+    /// A control sequence `ESC [ <params> <final>`
+    Csi(Csi),
+}
+
+/// A control sequence, like `ESC [ 1 ; 31 m`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Set for DEC private sequences, which start with `?`
+    pub private: bool,
+    /// The final character, selecting what the sequence does
+    pub action: char,
+}
+
+impl Csi {
+    const EMPTY: Self = Self {
+        params: [0; MAX_PARAMS],
+        len: 0,
+        private: false,
+        action: '\0',
+    };
+
+    /// Parameters as given, omitted ones are 0
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Returns parameter `i`, or `default` if it is omitted or 0
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        match self.params().get(i) {
+            Some(&param) if param != 0 => param,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// After `ESC (` and friends, which take one more character
+    EscapeCharset,
+    Csi,
+    /// Inside an OSC, DCS or similar string, which are skipped up to `BEL` or `ESC`
+    String,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi::EMPTY,
+        }
+    }
+
+    /// Feeds one character, returning an action once one is complete
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        const ESC: char = '\x1b';
+        match (self.state, c) {
+            // CAN and SUB abort any sequence
+            (_, '\x18' | '\x1a') => {
+                self.state = State::Ground;
+                None
+            }
+            (State::String, '\x07') => {
+                self.state = State::Ground;
+                None
+            }
+            (_, ESC) => {
+                self.state = State::Escape;
+                None
+            }
+            (State::String, _) => None,
+            // controls are executed even in the middle of a sequence
+            (_, '\0'..='\x1f') => Some(Action::Control(c)),
+            (_, '\x7f') => None,
+            (State::Ground, c) => Some(Action::Print(c)),
+            (State::Escape, '[') => {
+                self.csi = Csi::EMPTY;
+                self.state = State::Csi;
+                None
+            }
+            (State::Escape, ']' | 'P' | 'X' | '^' | '_') => {
+                self.state = State::String;
+                None
+            }
+            (State::Escape, '(' | ')' | '*' | '+' | '#' | '%') => {
+                self.state = State::EscapeCharset;
+                None
+            }
+            (State::Escape, c) => {
+                self.state = State::Ground;
+                Some(Action::Escape(c))
+            }
+            (State::EscapeCharset, _) => {
+                self.state = State::Ground;
+                None
+            }
+            (State::Csi, '0'..='9') => {
+                let csi = &mut self.csi;
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                let param = &mut csi.params[csi.len - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(c as u16 - '0' as u16);
+                None
+            }
+            (State::Csi, ';' | ':') => {
+                let csi = &mut self.csi;
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if csi.len < MAX_PARAMS {
+                    csi.len += 1;
+                }
+                None
+            }
+            (State::Csi, '?') => {
+                self.csi.private = true;
+                None
+            }
+            // other private markers and intermediates, none of which are supported
+            (State::Csi, '<' | '=' | '>' | ' '..='/') => None,
+            (State::Csi, c) => {
+                self.state = State::Ground;
+                self.csi.action = c;
+                Some(Action::Csi(self.csi))
+            }
+        }
+    }
+}
+
+/// Parses `input` from scratch, returning the first `N` actions
+#[cfg(test)]
+fn parse<const N: usize>(input: &str) -> [Option<Action>; N] {
+    let mut parser = Parser::new();
+    let mut actions = [None; N];
+    let parsed = input.chars().filter_map(|c| parser.advance(c));
+    for (slot, action) in actions.iter_mut().zip(parsed) {
+        *slot = Some(action);
+    }
+    actions
+}
+
+#[test_case]
+fn test_parse_csi() {
+    let [Some(Action::Csi(csi)), print, None] = parse::<3>("\x1b[;12Hx") else {
+        panic!("expected a control sequence");
+    };
+    assert_eq!(csi.action, 'H');
+    assert_eq!(csi.params(), &[0, 12]);
+    assert_eq!(csi.param(0, 1), 1);
+    assert_eq!(print, Some(Action::Print('x')));
+}
+
+#[test_case]
+fn test_parse_controls_and_strings() {
+    let [print, Some(Action::Csi(csi)), newline, None] = parse::<4>("a\x1b]0;title\x07\x1b[?25l\n")
+    else {
+        panic!("expected a control sequence");
+    };
+    assert_eq!(print, Some(Action::Print('a')));
+    assert!(csi.private);
+    assert_eq!((csi.action, csi.params()), ('l', &[25][..]));
+    assert_eq!(newline, Some(Action::Control('\n')));
+}
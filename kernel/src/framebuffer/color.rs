@@ -0,0 +1,93 @@
+//! RGB colours and the terminal palette
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+    pub const RED: Self = Self::rgb(205, 49, 49);
+    pub const GREEN: Self = Self::rgb(13, 188, 121);
+    pub const YELLOW: Self = Self::rgb(229, 229, 16);
+    pub const BLUE: Self = Self::rgb(36, 114, 200);
+    pub const MAGENTA: Self = Self::rgb(188, 63, 188);
+    pub const CYAN: Self = Self::rgb(17, 168, 205);
+    pub const LIGHT_GRAY: Self = Self::rgb(204, 204, 204);
+    pub const DARK_GRAY: Self = Self::rgb(118, 118, 118);
+    pub const BRIGHT_RED: Self = Self::rgb(241, 76, 76);
+    pub const BRIGHT_GREEN: Self = Self::rgb(35, 209, 139);
+    pub const BRIGHT_YELLOW: Self = Self::rgb(245, 245, 67);
+    pub const BRIGHT_BLUE: Self = Self::rgb(59, 142, 234);
+    pub const BRIGHT_MAGENTA: Self = Self::rgb(214, 112, 214);
+    pub const BRIGHT_CYAN: Self = Self::rgb(41, 184, 219);
+    pub const WHITE: Self = Self::rgb(242, 242, 242);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Puts `over` on top of `self`, with `alpha` 0 being transparent and 255 opaque
+    pub fn blend(self, over: Color, alpha: u8) -> Color {
+        let mix = |under: u8, over: u8| {
+            let alpha = u16::from(alpha);
+            ((u16::from(under) * (255 - alpha) + u16::from(over) * alpha + 127) / 255) as u8
+        };
+        Self {
+            r: mix(self.r, over.r),
+            g: mix(self.g, over.g),
+            b: mix(self.b, over.b),
+        }
+    }
+
+    /// Perceived brightness, for grayscale framebuffers
+    pub fn luma(self) -> u8 {
+        ((u16::from(self.r) * 77 + u16::from(self.g) * 150 + u16::from(self.b) * 29) >> 8) as u8
+    }
+
+    /// Looks up a colour of the xterm 256 colour palette: the 16 ANSI colours, a 6x6x6 colour
+    /// cube and 24 shades of gray
+    pub fn indexed(index: u8) -> Color {
+        const ANSI: [Color; 16] = [
+            Color::BLACK,
+            Color::RED,
+            Color::GREEN,
+            Color::YELLOW,
+            Color::BLUE,
+            Color::MAGENTA,
+            Color::CYAN,
+            Color::LIGHT_GRAY,
+            Color::DARK_GRAY,
+            Color::BRIGHT_RED,
+            Color::BRIGHT_GREEN,
+            Color::BRIGHT_YELLOW,
+            Color::BRIGHT_BLUE,
+            Color::BRIGHT_MAGENTA,
+            Color::BRIGHT_CYAN,
+            Color::WHITE,
+        ];
+        let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+        match index {
+            0..=15 => ANSI[usize::from(index)],
+            16..=231 => {
+                let i = index - 16;
+                Color::rgb(level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            232..=255 => {
+                let gray = 8 + (index - 232) * 10;
+                Color::rgb(gray, gray, gray)
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_blend() {
+    let under = Color::rgb(0, 100, 255);
+    let over = Color::rgb(255, 0, 255);
+    assert_eq!(under.blend(over, 0), under);
+    assert_eq!(under.blend(over, 255), over);
+    assert_eq!(under.blend(over, 128), Color::rgb(128, 50, 255));
+}
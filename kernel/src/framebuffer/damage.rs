@@ -0,0 +1,128 @@
+//! Tracking of the parts of the back buffer that changed since the last flush
+
+/// An area of the screen in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Exclusive
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    /// Exclusive
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    /// Smallest rectangle containing both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    /// The overlapping part, empty if there is none
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= x || bottom <= y {
+            return Rect::default();
+        }
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Whether the two overlap or share an edge
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+}
+
+/// Maximum number of separate dirty rectangles, more get merged
+const MAX_RECTS: usize = 16;
+
+/// A set of dirty rectangles.
+///
+/// Rectangles that touch are merged, so a line of text becomes a single rectangle.
+pub struct Damage {
+    rects: [Rect; MAX_RECTS],
+    len: usize,
+}
+
+impl Damage {
+    pub const fn new() -> Self {
+        Self {
+            rects: [Rect::new(0, 0, 0, 0); MAX_RECTS],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        let rects = &mut self.rects[..self.len];
+        if let Some(other) = rects.iter_mut().find(|other| other.touches(&rect)) {
+            *other = other.union(&rect);
+        } else if self.len < MAX_RECTS {
+            self.rects[self.len] = rect;
+            self.len += 1;
+        } else {
+            // out of room, flushing a bit more than needed is cheaper than tracking it
+            let last = &mut self.rects[MAX_RECTS - 1];
+            *last = last.union(&rect);
+        }
+    }
+
+    /// Returns the dirty rectangles and starts over
+    pub fn take(&mut self) -> impl Iterator<Item = Rect> {
+        let (rects, len) = (self.rects, self.len);
+        self.len = 0;
+        rects.into_iter().take(len)
+    }
+}
+
+#[test_case]
+fn test_damage_merges_touching_rects() {
+    let mut damage = Damage::new();
+    damage.add(Rect::new(0, 0, 8, 16));
+    damage.add(Rect::new(8, 0, 8, 16));
+    damage.add(Rect::new(100, 100, 1, 1));
+    let mut rects = damage.take();
+    assert_eq!(rects.next(), Some(Rect::new(0, 0, 16, 16)));
+    assert_eq!(rects.next(), Some(Rect::new(100, 100, 1, 1)));
+    assert_eq!(rects.next(), None);
+    assert!(damage.is_empty());
+}
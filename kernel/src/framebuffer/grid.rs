@@ -0,0 +1,135 @@
+//! The character grid behind the console, the framebuffer shows a rendering of it.
+use super::color::Color;
+
+/// A colour as selected by SGR sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermColor {
+    Default,
+    /// Index into the 256 colour palette, see [`Color::indexed`]
+    Indexed(u8),
+    Rgb(Color),
+}
+
+impl TermColor {
+    /// The colour to draw, `default` being the console's default colour for this use
+    pub fn resolve(self, default: Color) -> Color {
+        match self {
+            Self::Default => default,
+            Self::Indexed(index) => Color::indexed(index),
+            Self::Rgb(color) => color,
+        }
+    }
+}
+
+/// How a cell is drawn, set with SGR (`ESC [ ... m`) sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub fg: TermColor,
+    pub bg: TermColor,
+    pub bold: bool,
+    pub underline: bool,
+    /// Swaps foreground and background
+    pub reverse: bool,
+}
+
+impl Attributes {
+    pub const DEFAULT: Self = Self {
+        fg: TermColor::Default,
+        bg: TermColor::Default,
+        bold: false,
+        underline: false,
+        reverse: false,
+    };
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub attrs: Attributes,
+}
+
+impl Cell {
+    pub const BLANK: Self = Self {
+        c: ' ',
+        attrs: Attributes::DEFAULT,
+    };
+
+    /// An erased cell, which keeps the background colour like xterm does
+    pub fn erased(attrs: Attributes) -> Self {
+        Self {
+            c: ' ',
+            attrs: Attributes {
+                bg: attrs.bg,
+                ..Attributes::DEFAULT
+            },
+        }
+    }
+}
+
+/// Rows of cells, stored row by row
+pub struct Grid {
+    cells: &'static mut [Cell],
+    columns: usize,
+    rows: usize,
+}
+
+impl Grid {
+    /// Uses the start of `cells` for a grid of the given size
+    pub fn new(cells: &'static mut [Cell], columns: usize, rows: usize) -> Self {
+        assert!(columns * rows <= cells.len(), "grid storage too small");
+        let mut grid = Self {
+            cells,
+            columns,
+            rows,
+        };
+        grid.clear(Cell::BLANK);
+        grid
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> Cell {
+        self.cells[row * self.columns + column]
+    }
+
+    pub fn set(&mut self, column: usize, row: usize, cell: Cell) {
+        self.cells[row * self.columns + column] = cell;
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut [Cell] {
+        &mut self.cells[row * self.columns..(row + 1) * self.columns]
+    }
+
+    pub fn clear(&mut self, blank: Cell) {
+        self.cells[..self.columns * self.rows].fill(blank);
+    }
+
+    /// Moves all rows up by `n`, filling the rows at the bottom with `blank`
+    pub fn scroll_up(&mut self, n: usize, blank: Cell) {
+        let n = n.min(self.rows);
+        let end = self.columns * self.rows;
+        self.cells.copy_within(n * self.columns..end, 0);
+        self.cells[end - n * self.columns..end].fill(blank);
+    }
+
+    /// Moves all rows down by `n`, filling the rows at the top with `blank`
+    pub fn scroll_down(&mut self, n: usize, blank: Cell) {
+        let n = n.min(self.rows);
+        let end = self.columns * self.rows;
+        self.cells
+            .copy_within(..end - n * self.columns, n * self.columns);
+        self.cells[..n * self.columns].fill(blank);
+    }
+}
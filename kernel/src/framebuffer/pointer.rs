@@ -0,0 +1,88 @@
+//! The mouse pointer.
+//!
+//! It never goes into the back buffer, it's drawn on top of it onto the screen while flushing.
+//! So whatever is under it stays in the back buffer, and moving or hiding the pointer just
+//! flushes that area again.
+use super::Rect;
+use crate::graphics::{Bitmap, Color, Rgba};
+
+/// The default arrow, `X` is the outline and `.` the fill
+const ARROW: [&str; 19] = [
+    "X           ",
+    "XX          ",
+    "X.X         ",
+    "X..X        ",
+    "X...X       ",
+    "X....X      ",
+    "X.....X     ",
+    "X......X    ",
+    "X.......X   ",
+    "X........X  ",
+    "X.........X ",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X    ",
+    "X..XX..X    ",
+    "X.X  X..X   ",
+    "XX   X..X   ",
+    "      X..X  ",
+    "      XXXX  ",
+];
+
+fn arrow() -> Bitmap {
+    let mut bitmap = Bitmap::new(ARROW[0].len(), ARROW.len());
+    for (y, row) in ARROW.iter().enumerate() {
+        for (x, c) in row.bytes().enumerate() {
+            match c {
+                b'X' => bitmap.set(x, y, Rgba::opaque(Color::BLACK)),
+                b'.' => bitmap.set(x, y, Rgba::opaque(Color::WHITE)),
+                _ => {}
+            }
+        }
+    }
+    bitmap
+}
+
+pub(super) struct Pointer {
+    pub x: usize,
+    pub y: usize,
+    pub visible: bool,
+    /// Created on first use, as it needs the heap
+    sprite: Option<Bitmap>,
+    /// The pixel of the sprite that is at the pointer's position
+    hotspot: (usize, usize),
+    /// Moved, shown or changed since it was last drawn
+    pub dirty: bool,
+}
+
+impl Pointer {
+    pub const fn new() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            visible: false,
+            sprite: None,
+            hotspot: (0, 0),
+            dirty: false,
+        }
+    }
+
+    pub fn sprite(&mut self) -> &Bitmap {
+        self.sprite.get_or_insert_with(arrow)
+    }
+
+    pub fn set_sprite(&mut self, sprite: Bitmap, hotspot: (usize, usize)) {
+        self.sprite = Some(sprite);
+        self.hotspot = hotspot;
+    }
+
+    /// Where the sprite goes on the screen, not clipped
+    pub fn rect(&mut self) -> Rect {
+        let (x, y) = (
+            self.x.saturating_sub(self.hotspot.0),
+            self.y.saturating_sub(self.hotspot.1),
+        );
+        let sprite = self.sprite();
+        Rect::new(x, y, sprite.width(), sprite.height())
+    }
+}
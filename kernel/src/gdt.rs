@@ -1,42 +1,86 @@
+use crate::memory::vma::{self, VmaError};
+use core::ptr::addr_of_mut;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::paging::PageTableFlags;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 const STACK_SIZE: usize = 4096 * 5;
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
-pub const PAGE_FAULT_IST_INDEX: u16 = 1;
-pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 2;
+pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 1;
+
+/// Stacks in the TSS, by name. The name shows up in stack overflow reports.
+///
+/// Page faults have no stack of their own: they run on the faulting stack, so they can nest,
+/// e.g. when the handler touches the heap. A page fault that can't be pushed because the
+/// stack overflowed into its guard page becomes a double fault instead.
+const STACKS: [(&str, Stack); 3] = [
+    ("privilege level 0 stack", Stack::Privilege(0)),
+    (
+        "double fault stack",
+        Stack::Interrupt(DOUBLE_FAULT_IST_INDEX),
+    ),
+    (
+        "general protection fault stack",
+        Stack::Interrupt(GENERAL_PROTECTION_FAULT_IST_INDEX),
+    ),
+];
+
+#[derive(Clone, Copy)]
+enum Stack {
+    Privilege(usize),
+    Interrupt(u16),
+}
 
 // some code from https://github.com/vinc/moros/blob/trunk/src/sys/gdt.rs
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.privilege_stack_table[0] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// Only written before the TSS is loaded, and by [`init_stacks`] with interrupts disabled
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE
-        };
-        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss
-    };
+/// Sets the stacks used until [`init_stacks`] runs. These are plain statics without guard
+/// pages, so an overflow corrupts whatever follows them.
+fn init_boot_stacks(tss: &mut TaskStateSegment) {
+    static mut BOOT_STACKS: [[u8; STACK_SIZE]; STACKS.len()] = [[0; STACK_SIZE]; STACKS.len()];
+
+    for (i, (_, stack)) in STACKS.iter().enumerate() {
+        let top = VirtAddr::from_ptr(unsafe { addr_of_mut!(BOOT_STACKS[i]) }) + STACK_SIZE;
+        set_stack(tss, *stack, top);
+    }
+}
+
+fn set_stack(tss: &mut TaskStateSegment, stack: Stack, top: VirtAddr) {
+    match stack {
+        Stack::Privilege(i) => tss.privilege_stack_table[i] = top,
+        Stack::Interrupt(i) => tss.interrupt_stack_table[i as usize] = top,
+    }
+}
+
+/// Moves the TSS stacks to mapped memory, each with an unmapped guard page below it.
+///
+/// Needs [`crate::memory::install`]. Overflowing into a guard page is reported as a stack
+/// overflow by the double fault handler.
+pub fn init_stacks() -> Result<(), VmaError> {
+    for (name, stack) in STACKS {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let bottom = vma::reserve(name, STACK_SIZE as u64, flags)?;
+        // stacks of exception handlers can't rely on page faults to be mapped
+        vma::populate(bottom)?;
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            set_stack(&mut *addr_of_mut!(TSS), stack, bottom + STACK_SIZE);
+        });
+    }
+    Ok(())
+}
+
+lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let tss = unsafe { &mut *addr_of_mut!(TSS) };
+        init_boot_stacks(tss);
+
         let mut gdt = GlobalDescriptorTable::new();
         let code = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss = gdt.add_entry(Descriptor::tss_segment(tss));
         let data = gdt.add_entry(Descriptor::kernel_data_segment());
         let user_code = gdt.add_entry(Descriptor::user_code_segment());
         let user_data = gdt.add_entry(Descriptor::user_data_segment());
@@ -0,0 +1,275 @@
+//! 2D drawing on anything that implements [`Canvas`], like the framebuffer or a [`Bitmap`].
+//!
+//! Shapes are drawn through a [`Painter`], which clips them and reports the touched area to
+//! the canvas, so the framebuffer only flushes what changed.
+pub mod qoi;
+
+pub use crate::framebuffer::{Color, Rect};
+
+use alloc::{vec, vec::Vec};
+
+/// Something with pixels to draw on
+pub trait Canvas {
+    /// Width and height in pixels
+    fn size(&self) -> (usize, usize);
+    /// Sets a pixel, the position is always in bounds
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color);
+    /// Reads a pixel back, for blending. The position is always in bounds.
+    fn pixel(&self, x: usize, y: usize) -> Color;
+    /// Called after drawing into `rect`, for canvases that track changes
+    fn damage(&mut self, _rect: Rect) {}
+}
+
+/// A colour with opacity, 0 being transparent and 255 opaque
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub const TRANSPARENT: Self = Self::new(0, 0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn opaque(color: Color) -> Self {
+        Self::new(color.r, color.g, color.b, 255)
+    }
+
+    pub const fn color(self) -> Color {
+        Color::rgb(self.r, self.g, self.b)
+    }
+}
+
+/// An image in memory, also usable as an offscreen [`Canvas`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+}
+
+impl Bitmap {
+    /// A fully transparent bitmap
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Rgba::TRANSPARENT; width * height],
+        }
+    }
+
+    /// Wraps pixels stored row by row
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Rgba>) -> Option<Self> {
+        (pixels.len() == width * height).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Rgba {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Rgba) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn pixels(&self) -> &[Rgba] {
+        &self.pixels
+    }
+}
+
+impl Canvas for Bitmap {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.set(x, y, Rgba::opaque(color));
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        self.get(x, y).color()
+    }
+}
+
+/// Draws shapes on a [`Canvas`], clipped to a rectangle.
+///
+/// Positions are signed, so shapes can reach over the edges.
+pub struct Painter<'a, C: Canvas + ?Sized> {
+    canvas: &'a mut C,
+    clip: Rect,
+}
+
+impl<'a, C: Canvas + ?Sized> Painter<'a, C> {
+    /// A painter for the whole canvas
+    pub fn new(canvas: &'a mut C) -> Self {
+        let (width, height) = canvas.size();
+        Self {
+            canvas,
+            clip: Rect::new(0, 0, width, height),
+        }
+    }
+
+    /// Restricts drawing to `rect`, within the current clip rectangle
+    pub fn clip(mut self, rect: Rect) -> Self {
+        self.clip = self.clip.intersection(&rect);
+        self
+    }
+
+    fn contains(&self, x: isize, y: isize) -> bool {
+        let clip = &self.clip;
+        x >= clip.x as isize
+            && y >= clip.y as isize
+            && x < clip.right() as isize
+            && y < clip.bottom() as isize
+    }
+
+    /// The part of a shape's bounding box that is drawn, empty if none
+    fn visible(&self, x: isize, y: isize, width: isize, height: isize) -> Rect {
+        let (x0, y0) = (x.max(0), y.max(0));
+        let (x1, y1) = ((x + width).max(0), (y + height).max(0));
+        let rect = Rect::new(
+            x0 as usize,
+            y0 as usize,
+            (x1 - x0).max(0) as usize,
+            (y1 - y0).max(0) as usize,
+        );
+        rect.intersection(&self.clip)
+    }
+
+    fn plot(&mut self, x: isize, y: isize, color: Color) {
+        if self.contains(x, y) {
+            self.canvas.set_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: Color) {
+        let rect = self.visible(x, y, width as isize, height as isize);
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                self.canvas.set_pixel(x, y, color);
+            }
+        }
+        self.canvas.damage(rect);
+    }
+
+    /// A rectangle outline, one pixel wide
+    pub fn stroke_rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: Color) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (right, bottom) = (x + width as isize - 1, y + height as isize - 1);
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, bottom, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(right, y, 1, height, color);
+    }
+
+    /// A line including both end points, with Bresenham's algorithm
+    pub fn line(&mut self, from: (isize, isize), to: (isize, isize), color: Color) {
+        let (mut x, mut y) = from;
+        let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+        let (step_x, step_y) = ((to.0 - x).signum(), (to.1 - y).signum());
+        let mut error = dx + dy;
+        loop {
+            self.plot(x, y, color);
+            if (x, y) == to {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+        let (left, top) = (from.0.min(to.0), from.1.min(to.1));
+        let damaged = self.visible(left, top, dx + 1, -dy + 1);
+        self.canvas.damage(damaged);
+    }
+
+    /// A circle outline, with the midpoint algorithm
+    pub fn circle(&mut self, center: (isize, isize), radius: isize, color: Color) {
+        let (cx, cy) = center;
+        let (mut x, mut y, mut error) = (radius, 0, 1 - radius);
+        while x >= y {
+            for (px, py) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                self.plot(cx + px, cy + py, color);
+            }
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+        self.damage_circle(center, radius);
+    }
+
+    pub fn fill_circle(&mut self, center: (isize, isize), radius: isize, color: Color) {
+        let (cx, cy) = center;
+        for dy in -radius..=radius {
+            // widest dx with dx² + dy² <= r²
+            let mut dx = 0;
+            while (dx + 1) * (dx + 1) + dy * dy <= radius * radius {
+                dx += 1;
+            }
+            let rect = self.visible(cx - dx, cy + dy, 2 * dx + 1, 1);
+            for x in rect.x..rect.right() {
+                self.canvas.set_pixel(x, rect.y, color);
+            }
+        }
+        self.damage_circle(center, radius);
+    }
+
+    fn damage_circle(&mut self, (cx, cy): (isize, isize), radius: isize) {
+        let size = 2 * radius + 1;
+        let damaged = self.visible(cx - radius, cy - radius, size, size);
+        self.canvas.damage(damaged);
+    }
+
+    /// Draws a bitmap with its top left corner at `x`, `y`, blending by its alpha
+    pub fn blit(&mut self, x: isize, y: isize, bitmap: &Bitmap) {
+        let rect = self.visible(x, y, bitmap.width as isize, bitmap.height as isize);
+        for dst_y in rect.y..rect.bottom() {
+            for dst_x in rect.x..rect.right() {
+                let src = bitmap.get((dst_x as isize - x) as usize, (dst_y as isize - y) as usize);
+                let color = match src.a {
+                    0 => continue,
+                    255 => src.color(),
+                    alpha => self.canvas.pixel(dst_x, dst_y).blend(src.color(), alpha),
+                };
+                self.canvas.set_pixel(dst_x, dst_y, color);
+            }
+        }
+        self.canvas.damage(rect);
+    }
+}
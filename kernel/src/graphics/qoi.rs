@@ -0,0 +1,86 @@
+//! Decoder for the "Quite OK Image" format, see <https://qoiformat.org/qoi-specification.pdf>
+use super::{Bitmap, Rgba};
+use alloc::vec::Vec;
+
+const MAGIC: &[u8; 4] = b"qoif";
+const HEADER_SIZE: usize = 14;
+/// Refuse images with more pixels, so a bad header can't exhaust the heap
+const MAX_PIXELS: usize = 4096 * 4096;
+
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const OP_INDEX: u8 = 0b00;
+const OP_DIFF: u8 = 0b01;
+const OP_LUMA: u8 = 0b10;
+const OP_RUN: u8 = 0b11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QoiError {
+    /// Doesn't start with `qoif`
+    BadMagic,
+    TooLarge,
+    /// The data ends before all pixels are decoded
+    Truncated,
+}
+
+pub fn decode(data: &[u8]) -> Result<Bitmap, QoiError> {
+    if data.len() < HEADER_SIZE {
+        return Err(QoiError::Truncated);
+    }
+    if &data[..4] != MAGIC {
+        return Err(QoiError::BadMagic);
+    }
+    let dimension = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap()) as usize;
+    let (width, height) = (dimension(4), dimension(8));
+    // channels and colorspace don't change how the data is decoded
+    let pixel_count = width
+        .checked_mul(height)
+        .filter(|&count| count <= MAX_PIXELS)
+        .ok_or(QoiError::TooLarge)?;
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let mut index = [Rgba::TRANSPARENT; 64];
+    let mut pixel = Rgba::new(0, 0, 0, 255);
+    let mut bytes = data[HEADER_SIZE..].iter().copied();
+    let mut next = || bytes.next().ok_or(QoiError::Truncated);
+
+    while pixels.len() < pixel_count {
+        let byte = next()?;
+        let mut run = 1;
+        match (byte, byte >> 6) {
+            (OP_RGB, _) => {
+                pixel = Rgba::new(next()?, next()?, next()?, pixel.a);
+            }
+            (OP_RGBA, _) => {
+                pixel = Rgba::new(next()?, next()?, next()?, next()?);
+            }
+            (_, OP_INDEX) => pixel = index[usize::from(byte)],
+            (_, OP_DIFF) => {
+                let diff = |shift: u8| ((byte >> shift) & 0b11).wrapping_sub(2);
+                pixel.r = pixel.r.wrapping_add(diff(4));
+                pixel.g = pixel.g.wrapping_add(diff(2));
+                pixel.b = pixel.b.wrapping_add(diff(0));
+            }
+            (_, OP_LUMA) => {
+                let dg = (byte & 0x3f).wrapping_sub(32);
+                let second = next()?;
+                let dr = (second >> 4).wrapping_sub(8).wrapping_add(dg);
+                let db = (second & 0x0f).wrapping_sub(8).wrapping_add(dg);
+                pixel.r = pixel.r.wrapping_add(dr);
+                pixel.g = pixel.g.wrapping_add(dg);
+                pixel.b = pixel.b.wrapping_add(db);
+            }
+            (_, OP_RUN) => run = usize::from(byte & 0x3f) + 1,
+            _ => unreachable!(),
+        }
+        index[hash(pixel)] = pixel;
+        let run = run.min(pixel_count - pixels.len());
+        pixels.resize(pixels.len() + run, pixel);
+    }
+    Ok(Bitmap::from_pixels(width, height, pixels).expect("decoded every pixel"))
+}
+
+fn hash(pixel: Rgba) -> usize {
+    let Rgba { r, g, b, a } = pixel;
+    (usize::from(r) * 3 + usize::from(g) * 5 + usize::from(b) * 7 + usize::from(a) * 11) % 64
+}
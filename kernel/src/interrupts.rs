@@ -1,4 +1,6 @@
-use crate::{gdt, hlt_loop, println, serial_println};
+use crate::fault::{self, FaultName};
+use crate::gdt;
+use crate::memory::vma::{self, FaultResolution};
 use lazy_static::lazy_static;
 use paste::paste;
 use pic8259::ChainedPics;
@@ -9,21 +11,22 @@ pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+/// The first PIC's line the second one is chained to
+const CASCADE_IRQ: u8 = 2;
 
+/// Generates an exception handler that applies the [fault policy](crate::fault)
 macro_rules! ehand {
     ($name:ident) => {
         paste! {
             extern "x86-interrupt" fn [<$name _handler>](stackframe: InterruptStackFrame) {
-                println!("EXCEPTION: {}:\n{:#?}", stringify!($name:upper), stackframe);
-                serial_println!("EXCEPTION: {}:\n{:#?}", stringify!($name:upper), stackframe);
+                fault::handle(FaultName(stringify!([<$name:upper>])), None, &stackframe);
             }
         }
     };
     (code $name:ident) => {
         paste! {
             extern "x86-interrupt" fn [<$name _handler>](stackframe: InterruptStackFrame, ecode: u64) {
-                println!("EXCEPTION: {} ({}):\n{:#?}", stringify!([<$name:upper>]).replace("_", " "), ecode, stackframe);
-                serial_println!("EXCEPTION: {} ({}):\n{:#?}", stringify!([<$name:upper>]).replace("_", " "), ecode, stackframe);
+                fault::handle(FaultName(stringify!([<$name:upper>])), Some(ecode), &stackframe);
             }
         }
     };
@@ -38,11 +41,7 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        unsafe {
-            idt.page_fault
-                .set_handler_fn(page_fault_handler)
-                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
-        }
+        idt.page_fault.set_handler_fn(page_fault_handler);
         ehand!(division);
         idt.divide_error.set_handler_fn(division_handler);
         ehand!(bound_range);
@@ -76,6 +75,7 @@ lazy_static! {
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
         idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::Serial1.as_usize()].set_handler_fn(serial_interrupt_handler);
         idt
     };
 }
@@ -85,13 +85,22 @@ pub fn init_idt() {
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stackframe: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT:\n{:#?}", stackframe);
+    log::warn!("EXCEPTION: BREAKPOINT:\n{:#?}", stackframe);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stackframe: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    // most likely a fault while delivering a page fault for a guard page
+    if let Some(stack) = vma::guard_at(Cr2::read()) {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT: stack overflow in {}\n{:#?}",
+            stack, stackframe
+        );
+    }
     panic!("EXCEPTION: DOUBLE FAULT:\n{:#?}", stackframe);
 }
 
@@ -101,16 +110,31 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+    let addr = Cr2::read();
+    match vma::resolve_fault(addr, error_code) {
+        FaultResolution::Resolved => return,
+        FaultResolution::Guard(name) => log::error!("stack overflow in {} ({:?})", name, addr),
+        FaultResolution::Violation(name) => {
+            log::error!(
+                "page fault accessing {:?} in {}: {:?}",
+                addr,
+                name,
+                error_code
+            )
+        }
+        FaultResolution::Unmapped => {
+            log::error!("page fault accessing {:?}: {:?}", addr, error_code)
+        }
+    }
+    fault::handle(
+        FaultName("PAGE_FAULT"),
+        Some(error_code.bits()),
+        &stack_frame,
+    );
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    //println!(".");
-    //serial_println!("running...");
+    crate::time::tick();
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -118,21 +142,11 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
-    }
-
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
+    crate::task::keyboard::SCANCODES.push(scancode);
 
     unsafe {
         PICS.lock()
@@ -145,7 +159,7 @@ extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFr
 
     let mut port = PortReadOnly::new(0x60);
     let packet = unsafe { port.read() };
-    crate::task::mouse::write(packet);
+    crate::task::mouse::PACKETS.push(packet);
 
     unsafe {
         PICS.lock()
@@ -153,15 +167,28 @@ extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFr
     }
 }
 
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::serial::receive();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial1.as_u8());
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    /// COM1
+    Serial1 = PIC_1_OFFSET + 4,
     Mouse = PIC_1_OFFSET + 12,
 }
 
 impl InterruptIndex {
+    const ALL: [InterruptIndex; 4] = [Self::Timer, Self::Keyboard, Self::Serial1, Self::Mouse];
+
     fn as_u8(self) -> u8 {
         self as u8
     }
@@ -170,6 +197,33 @@ impl InterruptIndex {
     }
 }
 
+/// Remaps the PICs and unmasks exactly the IRQs that have a handler. `initialize` keeps the
+/// masks the firmware left, which may cover any of them, e.g. COM1.
+pub fn init_pics() {
+    let [mask1, mask2] = irq_masks();
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(mask1, mask2);
+    }
+}
+
+/// The masks of both PICs with the lines of [`InterruptIndex`] and the cascade cleared
+fn irq_masks() -> [u8; 2] {
+    let irqs = InterruptIndex::ALL.map(|index| index.as_u8() - PIC_1_OFFSET);
+    let mask = irqs
+        .iter()
+        .chain([&CASCADE_IRQ])
+        .fold(u16::MAX, |mask, irq| mask & !(1 << irq));
+    mask.to_le_bytes()
+}
+
+#[test_case]
+fn test_irq_masks() {
+    // timer, keyboard, cascade and COM1 on the first PIC, the mouse on the second
+    assert_eq!(irq_masks(), [0b1110_1000, 0b1110_1111]);
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
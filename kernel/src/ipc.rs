@@ -0,0 +1,393 @@
+//! Message passing between processes.
+//!
+//! A channel has two endpoints; messages sent on one arrive at the other, in order. A message
+//! is some bytes and some handles. Processes refer to endpoints through handles, small numbers
+//! that index their own [`HandleTable`], and sending a handle moves what it refers to into the
+//! receiver's table. That's also how access is granted: a process can only use the endpoints
+//! it created or was sent.
+//!
+//! Besides channel endpoints, handles can refer to [shared memory](shm), which several
+//! processes can have handles for at once.
+//!
+//! To let processes find each other, a server publishes a port under a name with [`bind`].
+//! [`connect`] then creates a new channel and sends one endpoint of it to the port, as a
+//! message without bytes and with that one handle.
+//!
+//! When an endpoint is dropped, e.g. because its process ended, the other one receives what's
+//! still queued and then reports [`IpcError::Closed`].
+use crate::task::sync::mpsc::{self, TryRecvError, TrySendError};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use spinning_top::Spinlock;
+
+pub mod shm;
+pub use shm::SharedMemory;
+
+/// Longest message, in bytes
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024;
+/// Most handles sent with a single message
+pub const MAX_MESSAGE_HANDLES: usize = 16;
+/// Most handles a process can have
+pub const MAX_HANDLES: usize = 1024;
+pub const MAX_PORT_NAME_LEN: usize = 64;
+/// Messages queued at an endpoint before senders get [`IpcError::Full`]
+const QUEUE_LEN: usize = 64;
+
+/// Ports by name, each the way to send to the endpoint [`bind`] returned
+static PORTS: Spinlock<BTreeMap<String, mpsc::Sender<Message>>> = Spinlock::new(BTreeMap::new());
+static NEXT_CHANNEL: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// An argument is out of range, or a handle is given twice or sent over its own channel
+    Invalid,
+    /// The handle doesn't exist
+    BadHandle,
+    /// No message is queued
+    Empty,
+    /// The other endpoint is gone
+    Closed,
+    /// The other endpoint has too many messages queued
+    Full,
+    /// The message doesn't fit the buffers, it stays queued
+    TooSmall { len: usize, handles: usize },
+    /// The message is too long or has too many handles
+    TooBig,
+    /// The process has [`MAX_HANDLES`] handles already
+    NoHandles,
+    /// A port with the name exists already
+    AddressInUse,
+    /// There's no port with the name
+    NotFound,
+    /// The handle refers to the wrong kind of object
+    WrongType,
+    /// The handle doesn't allow it, like writing with a read-only handle
+    AccessDenied,
+    /// The kernel heap is exhausted
+    NoMemory,
+}
+
+impl IpcError {
+    /// The negative number host functions return for it
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Invalid => -1,
+            Self::BadHandle => -2,
+            Self::Empty => -3,
+            Self::Closed => -4,
+            Self::Full => -5,
+            Self::TooSmall { .. } => -6,
+            Self::TooBig => -7,
+            Self::NoHandles => -8,
+            Self::AddressInUse => -9,
+            Self::NotFound => -10,
+            Self::WrongType => -11,
+            Self::AccessDenied => -12,
+            Self::NoMemory => -13,
+        }
+    }
+}
+
+/// Something a handle refers to
+pub enum Object {
+    Channel(Endpoint),
+    SharedMemory(SharedMemory),
+}
+
+pub struct Message {
+    pub bytes: Vec<u8>,
+    pub handles: Vec<Object>,
+}
+
+impl Message {
+    fn check_size(&self) -> Result<(), IpcError> {
+        if self.bytes.len() > MAX_MESSAGE_LEN || self.handles.len() > MAX_MESSAGE_HANDLES {
+            return Err(IpcError::TooBig);
+        }
+        Ok(())
+    }
+}
+
+/// One end of a channel
+pub struct Endpoint {
+    /// The same for both ends
+    channel: u64,
+    /// To the other end
+    tx: mpsc::Sender<Message>,
+    rx: mpsc::Receiver<Message>,
+    /// A message taken out of `rx` while waiting, it's received next
+    peeked: Option<Message>,
+}
+
+/// Creates a channel and returns its two endpoints
+pub fn channel() -> (Endpoint, Endpoint) {
+    let (tx_a, rx_a) = mpsc::channel(QUEUE_LEN);
+    let (tx_b, rx_b) = mpsc::channel(QUEUE_LEN);
+    let channel = NEXT_CHANNEL.fetch_add(1, Ordering::Relaxed);
+    let a = Endpoint {
+        channel,
+        tx: tx_b,
+        rx: rx_a,
+        peeked: None,
+    };
+    let b = Endpoint {
+        channel,
+        tx: tx_a,
+        rx: rx_b,
+        peeked: None,
+    };
+    (a, b)
+}
+
+impl Endpoint {
+    /// Queues a message at the other end without waiting
+    pub fn send(&self, message: Message) -> Result<(), IpcError> {
+        self.try_send(message).map_err(|(err, _)| err)
+    }
+
+    /// Like [`Endpoint::send`], but hands the message back when it wasn't sent
+    fn try_send(&self, message: Message) -> Result<(), (IpcError, Message)> {
+        if let Err(err) = message.check_size() {
+            return Err((err, message));
+        }
+        self.tx.try_send(message).map_err(|err| match err {
+            TrySendError::Full(message) => (IpcError::Full, message),
+            TrySendError::Closed(message) => (IpcError::Closed, message),
+        })
+    }
+
+    /// Takes the next message without waiting
+    pub fn try_recv(&mut self) -> Result<Message, IpcError> {
+        self.fill_peeked()?;
+        self.peeked.take().ok_or(IpcError::Empty)
+    }
+
+    /// Waits for the next message
+    pub async fn recv(&mut self) -> Result<Message, IpcError> {
+        poll_fn(|cx| self.poll_ready(cx)).await;
+        self.try_recv()
+    }
+
+    /// Ready when a message is queued or the other end is gone
+    pub fn poll_ready(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.peeked.is_some() {
+            return Poll::Ready(());
+        }
+        match self.rx.poll_recv(cx) {
+            Poll::Ready(message) => {
+                self.peeked = message;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// The next message, left queued
+    fn peek(&mut self) -> Result<&Message, IpcError> {
+        self.fill_peeked()?;
+        self.peeked.as_ref().ok_or(IpcError::Empty)
+    }
+
+    fn fill_peeked(&mut self) -> Result<(), IpcError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.rx.try_recv().map_err(|err| match err {
+                TryRecvError::Empty => IpcError::Empty,
+                TryRecvError::Closed => IpcError::Closed,
+            })?);
+        }
+        Ok(())
+    }
+}
+
+/// Publishes a port under `name` and returns the endpoint its connections arrive at. The
+/// name is free again once the endpoint is dropped.
+pub fn bind(name: &str) -> Result<Endpoint, IpcError> {
+    if name.is_empty() || name.len() > MAX_PORT_NAME_LEN {
+        return Err(IpcError::Invalid);
+    }
+    let mut ports = PORTS.lock();
+    if ports.get(name).is_some_and(|port| !port.is_closed()) {
+        return Err(IpcError::AddressInUse);
+    }
+    let (server, port) = channel();
+    // nobody reads what the server sends on the port, so sending there fails
+    let Endpoint { tx, .. } = port;
+    ports.insert(String::from(name), tx);
+    Ok(server)
+}
+
+/// Connects to the port published under `name`, returning the client's endpoint
+pub fn connect(name: &str) -> Result<Endpoint, IpcError> {
+    let mut ports = PORTS.lock();
+    let port = ports.get(name).ok_or(IpcError::NotFound)?;
+    let (client, server) = channel();
+    let message = Message {
+        bytes: Vec::new(),
+        handles: Vec::from([Object::Channel(server)]),
+    };
+    match port.try_send(message) {
+        Ok(()) => Ok(client),
+        Err(TrySendError::Full(_)) => Err(IpcError::Full),
+        Err(TrySendError::Closed(_)) => {
+            ports.remove(name);
+            Err(IpcError::NotFound)
+        }
+    }
+}
+
+/// The objects a process has handles for. Handles start at 1, 0 is never valid, and stay
+/// below `i32::MAX` so they can't be mistaken for error codes.
+#[derive(Default)]
+pub struct HandleTable {
+    objects: BTreeMap<u32, Object>,
+    last: u32,
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, object: Object) -> Result<u32, IpcError> {
+        if self.objects.len() >= MAX_HANDLES {
+            return Err(IpcError::NoHandles);
+        }
+        loop {
+            self.last = if self.last < i32::MAX as u32 {
+                self.last + 1
+            } else {
+                1
+            };
+            if !self.objects.contains_key(&self.last) {
+                break;
+            }
+        }
+        self.objects.insert(self.last, object);
+        Ok(self.last)
+    }
+
+    /// Removes a handle, dropping the object unless it's used
+    pub fn remove(&mut self, handle: u32) -> Result<Object, IpcError> {
+        self.objects.remove(&handle).ok_or(IpcError::BadHandle)
+    }
+
+    pub fn channel(&mut self, handle: u32) -> Result<&mut Endpoint, IpcError> {
+        match self.objects.get_mut(&handle) {
+            Some(Object::Channel(endpoint)) => Ok(endpoint),
+            Some(_) => Err(IpcError::WrongType),
+            None => Err(IpcError::BadHandle),
+        }
+    }
+
+    pub fn shared_memory(&self, handle: u32) -> Result<&SharedMemory, IpcError> {
+        match self.objects.get(&handle) {
+            Some(Object::SharedMemory(memory)) => Ok(memory),
+            Some(_) => Err(IpcError::WrongType),
+            None => Err(IpcError::BadHandle),
+        }
+    }
+
+    /// Adds another handle to the shared memory `handle`, which can't write to it if
+    /// `read_only` is set. Channel endpoints can't be duplicated.
+    pub fn duplicate(&mut self, handle: u32, read_only: bool) -> Result<u32, IpcError> {
+        let memory = self.shared_memory(handle)?.share(read_only);
+        self.insert(Object::SharedMemory(memory))
+    }
+
+    /// Sends bytes and handles on the channel `handle`. The handles are gone from the table
+    /// if it worked, and left alone if it didn't. Neither end of the channel can be sent over
+    /// it, the other end would end up queued at itself where nobody can receive it.
+    pub fn send(&mut self, handle: u32, bytes: Vec<u8>, handles: &[u32]) -> Result<(), IpcError> {
+        let channel = self.channel(handle)?.channel;
+        for (i, sent) in handles.iter().enumerate() {
+            if handles[..i].contains(sent) {
+                return Err(IpcError::Invalid);
+            }
+            match self.objects.get(sent) {
+                Some(Object::Channel(endpoint)) if endpoint.channel == channel => {
+                    return Err(IpcError::Invalid)
+                }
+                Some(_) => {}
+                None => return Err(IpcError::BadHandle),
+            }
+        }
+        let message = Message {
+            bytes,
+            handles: handles
+                .iter()
+                .filter_map(|h| self.objects.remove(h))
+                .collect(),
+        };
+        let result = self.channel(handle)?.try_send(message);
+        result.map_err(|(err, message)| {
+            self.objects
+                .extend(handles.iter().copied().zip(message.handles));
+            err
+        })
+    }
+
+    /// Receives a message from the channel `handle` without waiting, if it has at most `len`
+    /// bytes and `max_handles` handles. Returns its bytes and the handles it was given in this
+    /// table.
+    pub fn recv(
+        &mut self,
+        handle: u32,
+        len: usize,
+        max_handles: usize,
+    ) -> Result<(Vec<u8>, Vec<u32>), IpcError> {
+        let free = MAX_HANDLES - self.objects.len();
+        let endpoint = self.channel(handle)?;
+        let next = endpoint.peek()?;
+        if next.bytes.len() > len || next.handles.len() > max_handles {
+            return Err(IpcError::TooSmall {
+                len: next.bytes.len(),
+                handles: next.handles.len(),
+            });
+        }
+        if next.handles.len() > free {
+            return Err(IpcError::NoHandles);
+        }
+        let message = endpoint.try_recv()?;
+        let handles = message
+            .handles
+            .into_iter()
+            .map(|object| self.insert(object))
+            .collect::<Result<_, _>>()?;
+        Ok((message.bytes, handles))
+    }
+
+    /// Returns the index of the first of the channels `handles` that has a message queued or
+    /// whose other end is gone, or `None` if none has
+    pub fn ready(&mut self, handles: &[u32]) -> Result<Option<usize>, IpcError> {
+        for (i, handle) in handles.iter().enumerate() {
+            match self.channel(*handle)?.fill_peeked() {
+                Ok(()) | Err(IpcError::Closed) => return Ok(Some(i)),
+                Err(_) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits until one of the channels `handles` is ready, see [`HandleTable::ready`]
+    pub async fn wait(&mut self, handles: &[u32]) -> Result<usize, IpcError> {
+        if handles.is_empty() {
+            return Err(IpcError::Invalid);
+        }
+        poll_fn(|cx| {
+            for (i, handle) in handles.iter().enumerate() {
+                match self.channel(*handle) {
+                    Ok(endpoint) => {
+                        if endpoint.poll_ready(cx).is_ready() {
+                            return Poll::Ready(Ok(i));
+                        }
+                    }
+                    Err(err) => return Poll::Ready(Err(err)),
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
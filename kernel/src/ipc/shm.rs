@@ -0,0 +1,86 @@
+//! Shared memory: byte buffers several processes can have handles for, to pass large data
+//! like pixels without copying it through messages.
+//!
+//! WASM programs can't map a buffer into their own memory, they copy ranges in and out with
+//! `shm_read` and `shm_write`. Access is granted by duplicating the handle, optionally
+//! read-only, and sending the duplicate over a channel. The buffer is freed when the last
+//! handle to it is closed.
+use super::IpcError;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use spinning_top::Spinlock;
+
+/// Largest buffer, in bytes
+pub const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// A handle's view of a buffer
+#[derive(Clone)]
+pub struct SharedMemory {
+    buffer: Arc<Spinlock<Box<[u8]>>>,
+    writable: bool,
+}
+
+impl SharedMemory {
+    /// Allocates a zeroed buffer of `size` bytes
+    pub fn new(size: usize) -> Result<Self, IpcError> {
+        if size == 0 || size > MAX_SIZE {
+            return Err(IpcError::Invalid);
+        }
+        let mut bytes = Vec::new();
+        bytes
+            .try_reserve_exact(size)
+            .map_err(|_| IpcError::NoMemory)?;
+        bytes.resize(size, 0);
+        Ok(Self {
+            buffer: Arc::new(Spinlock::new(bytes.into_boxed_slice())),
+            writable: true,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.lock().len()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Another view of the same buffer, which can't write to it if this one can't or
+    /// `read_only` is set
+    pub fn share(&self, read_only: bool) -> Self {
+        Self {
+            buffer: Arc::clone(&self.buffer),
+            writable: self.writable && !read_only,
+        }
+    }
+
+    /// How many views of the buffer exist, it's freed when the last one is dropped
+    pub fn views(&self) -> usize {
+        Arc::strong_count(&self.buffer)
+    }
+
+    /// Copies `buf.len()` bytes at `offset` into `buf`
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), IpcError> {
+        let buffer = self.buffer.lock();
+        let range = range(offset, buf.len(), buffer.len())?;
+        buf.copy_from_slice(&buffer[range]);
+        Ok(())
+    }
+
+    /// Copies `bytes` to `offset`
+    pub fn write(&self, offset: usize, bytes: &[u8]) -> Result<(), IpcError> {
+        if !self.writable {
+            return Err(IpcError::AccessDenied);
+        }
+        let mut buffer = self.buffer.lock();
+        let range = range(offset, bytes.len(), buffer.len())?;
+        buffer[range].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+fn range(offset: usize, len: usize, size: usize) -> Result<core::ops::Range<usize>, IpcError> {
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(offset..end),
+        _ => Err(IpcError::Invalid),
+    }
+}
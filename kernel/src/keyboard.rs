@@ -0,0 +1,478 @@
+//! Keyboard events and layouts, switchable at runtime.
+//!
+//! The keyboard task ([`crate::task::keyboard`]) turns scancodes into [`KeyEvent`]s with a
+//! [`Decoder`], and sends them to everyone who [`subscribe`]d. Characters are decoded with
+//! [`ActiveLayout`], which follows whatever was last passed to [`set_layout`]: one of the
+//! layouts of `pc_keyboard`, or a [`Keymap`] from the ramdisk.
+//!
+//! The lock keys are shown on the keyboard's LEDs, and the key repeat of the keyboard can be
+//! changed with [`set_repeat`].
+mod keymap;
+
+pub use keymap::{Keymap, KeymapError};
+
+use crate::ps2::{self, Ps2Error};
+use crate::task::sync::broadcast::{self, Receiver, Sender};
+use alloc::{format, sync::Arc};
+use conquer_once::spin::OnceCell;
+use pc_keyboard::{layouts, HandleControl, Keyboard, KeyboardLayout, Modifiers, ScancodeSet1};
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+use spinning_top::Spinlock;
+use x86_64::instructions::interrupts;
+
+/// Where [`select`] looks for keymaps given by name
+const KEYMAP_DIR: &str = "keymaps";
+/// Number of events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 128;
+
+static EVENTS: OnceCell<Sender<KeyEvent>> = OnceCell::uninit();
+
+#[derive(Clone)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    De105,
+    /// French
+    Azerty,
+    Dvorak,
+    Colemak,
+    Custom(Arc<Keymap>),
+}
+
+impl Layout {
+    /// The built-in layouts, with the names [`Layout::by_name`] knows them by
+    pub const BUILTIN: [(&'static str, Layout); 6] = [
+        ("us", Self::Us104),
+        ("uk", Self::Uk105),
+        ("de", Self::De105),
+        ("fr", Self::Azerty),
+        ("dvorak", Self::Dvorak),
+        ("colemak", Self::Colemak),
+    ];
+
+    /// Looks up a built-in layout
+    pub fn by_name(name: &str) -> Option<Layout> {
+        Self::BUILTIN
+            .into_iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+            .map(|(_, layout)| layout)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Us104 => "us",
+            Self::Uk105 => "uk",
+            Self::De105 => "de",
+            Self::Azerty => "fr",
+            Self::Dvorak => "dvorak",
+            Self::Colemak => "colemak",
+            Self::Custom(keymap) => keymap.name(),
+        }
+    }
+}
+
+impl KeyboardLayout for Layout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match self {
+            Self::Us104 => layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::Uk105 => layouts::Uk105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::De105 => layouts::De105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::Azerty => layouts::Azerty.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::Dvorak => layouts::Dvorak104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::Colemak => layouts::Colemak.map_keycode(keycode, modifiers, handle_ctrl),
+            Self::Custom(keymap) => keymap.map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+static LAYOUT: Spinlock<Layout> = Spinlock::new(Layout::Us104);
+
+pub fn layout() -> Layout {
+    LAYOUT.lock().clone()
+}
+
+pub fn set_layout(layout: Layout) {
+    log::info!("keyboard layout: {}", layout.name());
+    *LAYOUT.lock() = layout;
+}
+
+/// Switches to a built-in layout by name, or else to a keymap from the ramdisk.
+///
+/// Keymaps are given by path, or by name for `keymaps/<name>.keymap`.
+pub fn select(name: &str) -> Result<(), KeymapError> {
+    let layout = match Layout::by_name(name) {
+        Some(layout) => layout,
+        None => {
+            let path = match name.contains('/') {
+                true => name.into(),
+                false => format!("{KEYMAP_DIR}/{name}.keymap"),
+            };
+            Layout::Custom(Arc::new(Keymap::load(&path)?))
+        }
+    };
+    set_layout(layout);
+    Ok(())
+}
+
+/// The layout to decode with, whatever [`set_layout`] chose last
+pub struct ActiveLayout;
+
+impl KeyboardLayout for ActiveLayout {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        LAYOUT.lock().map_keycode(keycode, modifiers, handle_ctrl)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyModifier {
+    Shift,
+    Ctrl,
+    Alt,
+    AltGr,
+    /// The Windows keys
+    Meta,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+impl KeyModifier {
+    pub const ALL: [KeyModifier; 8] = [
+        Self::Shift,
+        Self::Ctrl,
+        Self::Alt,
+        Self::AltGr,
+        Self::Meta,
+        Self::CapsLock,
+        Self::NumLock,
+        Self::ScrollLock,
+    ];
+
+    /// Bit in [`KeyModifiers`]
+    const fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// The modifier a held key adds, lock keys aren't held but toggled
+    fn held(code: KeyCode) -> Option<KeyModifier> {
+        match code {
+            KeyCode::LShift | KeyCode::RShift => Some(Self::Shift),
+            KeyCode::LControl | KeyCode::RControl | KeyCode::RControl2 => Some(Self::Ctrl),
+            KeyCode::LAlt | KeyCode::RAlt2 => Some(Self::Alt),
+            KeyCode::RAltGr => Some(Self::AltGr),
+            KeyCode::LWin | KeyCode::RWin => Some(Self::Meta),
+            _ => None,
+        }
+    }
+
+    fn toggled(code: KeyCode) -> Option<KeyModifier> {
+        match code {
+            KeyCode::CapsLock => Some(Self::CapsLock),
+            KeyCode::NumpadLock => Some(Self::NumLock),
+            KeyCode::ScrollLock => Some(Self::ScrollLock),
+            _ => None,
+        }
+    }
+}
+
+/// The modifier keys held down, and the lock keys that are on
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyModifiers(u8);
+
+impl KeyModifiers {
+    pub fn is_set(self, modifier: KeyModifier) -> bool {
+        self.0 & modifier.mask() != 0
+    }
+
+    pub fn set(&mut self, modifier: KeyModifier, on: bool) {
+        match on {
+            true => self.0 |= modifier.mask(),
+            false => self.0 &= !modifier.mask(),
+        }
+    }
+
+    /// One bit per modifier, in the order of [`KeyModifier::ALL`]
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// The LEDs showing the lock keys
+    pub fn leds(self) -> Leds {
+        Leds {
+            scroll_lock: self.is_set(KeyModifier::ScrollLock),
+            num_lock: self.is_set(KeyModifier::NumLock),
+            caps_lock: self.is_set(KeyModifier::CapsLock),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// `SingleShot` for keys that only report a press, like pause
+    pub state: KeyState,
+    /// Whether the key was already down, i.e. the keyboard repeats it
+    pub repeat: bool,
+    /// Modifiers after the event
+    pub modifiers: KeyModifiers,
+    /// What the key types in the current layout, for presses only
+    pub key: Option<DecodedKey>,
+    /// Uptime when the key was decoded
+    pub time_ms: u64,
+}
+
+fn events() -> &'static Sender<KeyEvent> {
+    EVENTS.get_or_init(|| broadcast::channel(EVENT_CAPACITY).0)
+}
+
+/// Starts receiving key events. Slow receivers lose the oldest events.
+pub fn subscribe() -> Receiver<KeyEvent> {
+    events().subscribe()
+}
+
+/// Sends an event to the subscribers
+pub fn publish(event: KeyEvent) {
+    // nobody listening is fine
+    let _ = events().send(event);
+}
+
+/// Every key `pc_keyboard` knows, row by row, so keys can be looked up by name.
+///
+/// A key's position is its [`key_number`], which WASM programs see. New keys go at the end,
+/// so the numbers don't change when `pc_keyboard` reorders its enum.
+#[rustfmt::skip]
+pub const KEYS: [KeyCode; 124] = {
+    use KeyCode::*;
+    [
+        Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, PrintScreen, SysRq, ScrollLock,
+        PauseBreak,
+        Oem8, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, OemMinus, OemPlus,
+        Backspace, Insert, Home, PageUp, NumpadLock, NumpadDivide, NumpadMultiply, NumpadSubtract,
+        Tab, Q, W, E, R, T, Y, U, I, O, P, Oem4, Oem6, Oem5, Oem7, Delete, End, PageDown, Numpad7,
+        Numpad8, Numpad9, NumpadAdd,
+        CapsLock, A, S, D, F, G, H, J, K, L, Oem1, Oem3, Return, Numpad4, Numpad5, Numpad6,
+        LShift, Z, X, C, V, B, N, M, OemComma, OemPeriod, Oem2, RShift, ArrowUp, Numpad1, Numpad2,
+        Numpad3, NumpadEnter,
+        LControl, LWin, LAlt, Spacebar, RAltGr, RWin, Apps, RControl, ArrowLeft, ArrowDown,
+        ArrowRight, Numpad0, NumpadPeriod,
+        Oem9, Oem10, Oem11, Oem12, Oem13,
+        PrevTrack, NextTrack, Mute, Calculator, Play, Stop, VolumeDown, VolumeUp, WWWHome,
+        PowerOnTestOk, TooManyKeys, RControl2, RAlt2,
+    ]
+};
+
+/// The stable number of a key, see [`KEYS`]
+pub fn key_number(code: KeyCode) -> u32 {
+    let index = KEYS.iter().position(|&key| key == code);
+    // every key is listed, see test_key_numbers
+    index.map_or(u32::MAX, |index| index as u32)
+}
+
+/// The keys [`KeyModifier::held`] knows
+const MODIFIER_KEYS: [KeyCode; 10] = [
+    KeyCode::LShift,
+    KeyCode::RShift,
+    KeyCode::LControl,
+    KeyCode::RControl,
+    KeyCode::RControl2,
+    KeyCode::LAlt,
+    KeyCode::RAlt2,
+    KeyCode::RAltGr,
+    KeyCode::LWin,
+    KeyCode::RWin,
+];
+
+/// Turns set 1 scancodes into [`KeyEvent`]s
+pub struct Decoder {
+    keyboard: Keyboard<ActiveLayout, ScancodeSet1>,
+    /// One bit per [`KeyCode`]
+    held: [u64; 4],
+    modifiers: KeyModifiers,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        let mut modifiers = KeyModifiers::default();
+        // like `pc_keyboard`, which starts with num lock on
+        modifiers.set(KeyModifier::NumLock, true);
+        Self {
+            keyboard: Keyboard::new(ScancodeSet1::new(), ActiveLayout, HandleControl::Ignore),
+            held: [0; 4],
+            modifiers,
+        }
+    }
+
+    /// Adds a scancode, returning the event it completes
+    pub fn add(&mut self, scancode: u8) -> Option<KeyEvent> {
+        // answers to our commands end up here too
+        if let ps2::ACK | ps2::RESEND = scancode {
+            return None;
+        }
+        let raw = self.keyboard.add_byte(scancode).ok()??;
+        let code = raw.code;
+        let state = raw.state;
+        let key = self.keyboard.process_keyevent(raw);
+
+        let repeat = state == KeyState::Down && self.is_held(code);
+        match state {
+            KeyState::Down => self.held[code as usize / 64] |= Self::bit(code),
+            KeyState::Up => self.held[code as usize / 64] &= !Self::bit(code),
+            KeyState::SingleShot => {}
+        }
+        if let Some(modifier) = KeyModifier::held(code) {
+            // the same modifier may still be held with the key on the other side
+            let held = MODIFIER_KEYS
+                .into_iter()
+                .any(|key| KeyModifier::held(key) == Some(modifier) && self.is_held(key));
+            self.modifiers.set(modifier, held);
+        }
+        if let (Some(modifier), KeyState::Down, false) = (KeyModifier::toggled(code), state, repeat)
+        {
+            let on = self.modifiers.is_set(modifier);
+            self.modifiers.set(modifier, !on);
+        }
+        Some(KeyEvent {
+            code,
+            state,
+            repeat,
+            modifiers: self.modifiers,
+            key,
+            time_ms: crate::time::uptime_ms(),
+        })
+    }
+
+    fn bit(code: KeyCode) -> u64 {
+        1 << (code as usize % 64)
+    }
+
+    fn is_held(&self, code: KeyCode) -> bool {
+        self.held[code as usize / 64] & Self::bit(code) != 0
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The Caps, Num and Scroll Lock LEDs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    fn bits(self) -> u8 {
+        u8::from(self.scroll_lock) | u8::from(self.num_lock) << 1 | u8::from(self.caps_lock) << 2
+    }
+}
+
+/// Turns the LEDs of the PS/2 keyboard on and off
+pub fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    interrupts::without_interrupts(|| {
+        ps2::send_keyboard(0xed)?;
+        ps2::send_keyboard(leds.bits())
+    })
+}
+
+/// Repeat rates the keyboard knows in tenths of characters per second, by their encoding
+const REPEAT_RATES: [u32; 32] = [
+    300, 267, 240, 218, 200, 185, 171, 160, 150, 133, 120, 109, 100, 92, 86, 80, 75, 67, 60, 55,
+    50, 46, 43, 40, 37, 33, 30, 27, 25, 23, 21, 20,
+];
+
+/// Sets how long a key is held before it repeats, and how many times a second it does then.
+///
+/// The keyboard only knows delays of 250 to 1000 ms in steps of 250 ms, and rates of 2 to 30
+/// per second, the closest ones are used.
+pub fn set_repeat(delay_ms: u32, rate: u32) -> Result<(), Ps2Error> {
+    let typematic = typematic(delay_ms, rate);
+    interrupts::without_interrupts(|| {
+        ps2::send_keyboard(0xf3)?;
+        ps2::send_keyboard(typematic)
+    })
+}
+
+/// The typematic byte: the delay in bits 5 and 6, the rate in bits 0 to 4
+fn typematic(delay_ms: u32, rate: u32) -> u8 {
+    let delay = ((delay_ms + 125) / 250).clamp(1, 4) - 1;
+    let rate = (0..REPEAT_RATES.len())
+        .min_by_key(|&i| REPEAT_RATES[i].abs_diff(rate * 10))
+        .unwrap_or(0);
+    (delay << 5) as u8 | rate as u8
+}
+
+#[test_case]
+fn test_decoder_modifiers() {
+    let mut decoder = Decoder::new();
+    let event = decoder.add(0x2a).unwrap(); // left shift
+    assert!(event.modifiers.is_set(KeyModifier::Shift));
+    let event = decoder.add(0x1e).unwrap(); // a
+    assert_eq!((event.code, event.state), (KeyCode::A, KeyState::Down));
+    assert!(!event.repeat);
+    assert!(decoder.add(0x1e).unwrap().repeat);
+    let event = decoder.add(0x9e).unwrap();
+    assert_eq!((event.state, event.key), (KeyState::Up, None));
+    let event = decoder.add(0xaa).unwrap();
+    assert!(!event.modifiers.is_set(KeyModifier::Shift));
+    // acknowledgements of LED commands aren't keys
+    assert_eq!(decoder.add(ps2::ACK), None);
+}
+
+#[test_case]
+fn test_decoder_lock_keys() {
+    let mut decoder = Decoder::new();
+    assert!(decoder
+        .add(0x3a)
+        .unwrap()
+        .modifiers
+        .is_set(KeyModifier::CapsLock));
+    // held down, it repeats without toggling again
+    assert!(decoder
+        .add(0x3a)
+        .unwrap()
+        .modifiers
+        .is_set(KeyModifier::CapsLock));
+    decoder.add(0xba);
+    let leds = decoder.add(0x3a).unwrap().modifiers.leds();
+    assert_eq!(
+        leds,
+        Leds {
+            scroll_lock: false,
+            num_lock: true,
+            caps_lock: false
+        }
+    );
+}
+
+#[test_case]
+fn test_key_numbers() {
+    assert_eq!(key_number(KeyCode::Escape), 0);
+    assert_eq!(key_number(KeyCode::A), 61);
+    assert_eq!(key_number(KeyCode::Spacebar), 96);
+    assert_eq!(key_number(KeyCode::RAlt2), 123);
+    for (index, &key) in KEYS.iter().enumerate() {
+        assert_eq!(key_number(key), index as u32, "{key:?} is listed twice");
+    }
+}
+
+#[test_case]
+fn test_typematic() {
+    // the default: 500 ms, 10.9 per second
+    assert_eq!(typematic(500, 11), 0x2b);
+    assert_eq!(typematic(0, 100), 0x00);
+    assert_eq!(typematic(5000, 0), 0x7f);
+}
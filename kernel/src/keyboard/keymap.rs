@@ -0,0 +1,173 @@
+//! Keymap files, which change keys of a built-in layout:
+//!
+//! ```text
+//! # a comment
+//! name workman
+//! base us
+//! # key   normal  shifted  altgr
+//! W       d       D
+//! Key2    2       @        U+00B2
+//! ```
+//!
+//! Keys are named like the variants of [`KeyCode`], other names are an error. A character is written as itself, or as
+//! `U+` and its hex code point, e.g. `U+0020` for space. `-` leaves that column to the base
+//! layout, just like keys that aren't listed.
+use super::{Layout, KEYS};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapError {
+    /// No such file on the ramdisk
+    NotFound,
+    NotUtf8,
+    Parse {
+        line: usize,
+        reason: &'static str,
+    },
+}
+
+struct Entry {
+    key: KeyCode,
+    normal: Option<char>,
+    shifted: Option<char>,
+    altgr: Option<char>,
+}
+
+pub struct Keymap {
+    name: String,
+    base: Layout,
+    entries: Vec<Entry>,
+}
+
+impl Keymap {
+    /// Loads a keymap file from the ramdisk
+    pub fn load(path: &str) -> Result<Keymap, KeymapError> {
+        let data = crate::ramdisk::get(path).ok_or(KeymapError::NotFound)?;
+        let text = core::str::from_utf8(data).map_err(|_| KeymapError::NotUtf8)?;
+        let name = path.rsplit('/').next().unwrap_or(path);
+        Self::parse(name.trim_end_matches(".keymap"), text)
+    }
+
+    /// Parses a keymap, `name` is used unless the file has a `name` line
+    pub fn parse(name: &str, text: &str) -> Result<Keymap, KeymapError> {
+        let mut keymap = Keymap {
+            name: name.into(),
+            base: Layout::Us104,
+            entries: Vec::new(),
+        };
+        for (index, line) in text.lines().enumerate() {
+            let error = |reason| KeymapError::Parse {
+                line: index + 1,
+                reason,
+            };
+            let mut fields = line.split_whitespace();
+            let Some(first) = fields.next().filter(|first| !first.starts_with('#')) else {
+                continue;
+            };
+            match first {
+                "name" => keymap.name = fields.next().ok_or(error("missing name"))?.into(),
+                "base" => {
+                    let base = fields.next().ok_or(error("missing base layout"))?;
+                    keymap.base = Layout::by_name(base).ok_or(error("unknown base layout"))?;
+                }
+                key => {
+                    let key = key_by_name(key).ok_or(error("unknown key"))?;
+                    let mut column = || match fields.next() {
+                        None | Some("-") => Ok(None),
+                        Some(field) => parse_char(field).map(Some).ok_or(error("bad character")),
+                    };
+                    let entry = Entry {
+                        key,
+                        normal: column()?,
+                        shifted: column()?,
+                        altgr: column()?,
+                    };
+                    if fields.next().is_some() {
+                        return Err(error("too many columns"));
+                    }
+                    keymap.entries.retain(|other| other.key != entry.key);
+                    keymap.entries.push(entry);
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn entry(&self, keycode: KeyCode) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.key == keycode)
+    }
+}
+
+impl KeyboardLayout for Keymap {
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        let Some(entry) = self.entry(keycode) else {
+            return self.base.map_keycode(keycode, modifiers, handle_ctrl);
+        };
+        // caps lock only shifts letters
+        let letter = entry.normal.is_some_and(char::is_alphabetic);
+        let shifted = modifiers.is_shifted() ^ (letter && modifiers.capslock);
+        let c = match (modifiers.ralt, shifted) {
+            (true, _) => entry.altgr,
+            (false, true) => entry.shifted,
+            (false, false) => entry.normal,
+        };
+        match c {
+            Some(c)
+                if modifiers.is_ctrl()
+                    && matches!(handle_ctrl, HandleControl::MapLettersToUnicode)
+                    && c.is_ascii_alphabetic() =>
+            {
+                DecodedKey::Unicode((c.to_ascii_lowercase() as u8 - b'a' + 1) as char)
+            }
+            Some(c) => DecodedKey::Unicode(c),
+            None => self.base.map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+fn key_by_name(name: &str) -> Option<KeyCode> {
+    KEYS.into_iter().find(|key| {
+        let mut key_name = KeyName::default();
+        fmt::write(&mut key_name, format_args!("{key:?}")).is_ok()
+            && key_name.bytes[..key_name.len] == *name.as_bytes()
+    })
+}
+
+fn parse_char(field: &str) -> Option<char> {
+    if let Some(hex) = field.strip_prefix("U+") {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    let mut chars = field.chars();
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+/// Room for the name of a [`KeyCode`], formatted without allocating
+#[derive(Default)]
+struct KeyName {
+    bytes: [u8; 24],
+    len: usize,
+}
+
+impl fmt::Write for KeyName {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
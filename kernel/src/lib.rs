@@ -12,13 +12,28 @@ use bootloader_api::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 extern crate alloc;
 pub mod allocator;
+pub mod backtrace;
+pub mod config;
+pub mod fault;
 pub mod framebuffer;
 pub mod gdt;
+pub mod graphics;
 pub mod interrupts;
+pub mod ipc;
+pub mod keyboard;
+pub mod logger;
 pub mod memory;
 pub mod mouse;
+pub mod pci;
+pub mod ps2;
+pub mod ramdisk;
 pub mod serial;
+pub mod service;
+pub mod shell;
 pub mod task;
+pub mod time;
+pub mod virtio;
+pub mod wasm;
 //pub mod vga_buffer;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -48,7 +63,7 @@ where
     fn run(&self) {
         print!("{}...\t", core::any::type_name::<T>());
         self();
-        println!("[ok]");
+        color_println!(framebuffer::Color::GREEN, "[ok]");
     }
 }
 
@@ -62,16 +77,19 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 
 #[allow(clippy::empty_loop)]
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
-    print!("[failed]\n");
+    color_print!(framebuffer::Color::RED, "[failed]\n");
     print!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
 pub fn init() {
+    logger::init(log::LevelFilter::Info);
     gdt::init();
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    serial::init();
+    interrupts::init_pics();
+    time::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -0,0 +1,273 @@
+//! Kernel logger for the [`log`] crate.
+//!
+//! Every record is formatted once into a line like `[    1.250] INFO  kernel::mouse: ...`,
+//! appended to an in-memory ring buffer (read it with [`dmesg`]) and written to every
+//! [`Sink`] whose level allows it. Sinks that need the heap, like a log file, can be added
+//! later with [`add_sink`].
+use crate::framebuffer::Color;
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spinning_top::Spinlock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Size of the ring buffer holding the most recent log lines
+const RING_SIZE: usize = 16 * 1024;
+/// Longer lines are truncated
+const MAX_LINE: usize = 1024;
+/// Maximum number of per-target level overrides
+const MAX_TARGET_FILTERS: usize = 8;
+
+static LOGGER: Logger = Logger;
+static RING: Spinlock<Ring> = Spinlock::new(Ring::new());
+static SINK_LEVELS: [AtomicUsize; 2] = [
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Info as usize),
+];
+static FILTERS: Spinlock<Filters> = Spinlock::new(Filters {
+    default: LevelFilter::Info,
+    targets: [None; MAX_TARGET_FILTERS],
+});
+static EXTRA_SINKS: Spinlock<Vec<Box<dyn LogSink>>> = Spinlock::new(Vec::new());
+
+/// Built-in log outputs, usable before the heap is initialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Serial,
+    Framebuffer,
+}
+
+/// An additional log output
+pub trait LogSink: Send {
+    /// Receives a formatted line, including the trailing newline
+    fn write(&mut self, level: Level, line: &str);
+}
+
+/// Installs the logger, with records above `level` being discarded
+pub fn init(level: LevelFilter) {
+    log::set_logger(&LOGGER).expect("logger already initialized");
+    set_level(level);
+}
+
+/// Sets the level for targets without an override
+pub fn set_level(level: LevelFilter) {
+    without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        filters.default = level;
+        filters.update_max_level();
+    });
+}
+
+/// Limits what gets written to one of the built-in sinks
+pub fn set_sink_level(sink: Sink, level: LevelFilter) {
+    SINK_LEVELS[sink as usize].store(level as usize, Ordering::Relaxed);
+}
+
+/// Overrides the level for records whose target starts with `prefix`, e.g. `kernel::mouse`.
+/// It can be above the [default](set_level), [`log::max_level`] is raised to match.
+///
+/// Returns `false` if all override slots are taken.
+pub fn set_target_level(prefix: &'static str, level: LevelFilter) -> bool {
+    without_interrupts(|| {
+        let mut filters = FILTERS.lock();
+        let targets = &mut filters.targets;
+        let index = targets
+            .iter()
+            .position(|f| matches!(f, Some((p, _)) if *p == prefix))
+            .or_else(|| targets.iter().position(Option::is_none));
+        let Some(index) = index else {
+            return false;
+        };
+        targets[index] = Some((prefix, level));
+        filters.update_max_level();
+        true
+    })
+}
+
+/// Adds an output, e.g. a file. Requires the heap.
+pub fn add_sink(sink: Box<dyn LogSink>) {
+    without_interrupts(|| EXTRA_SINKS.lock().push(sink));
+}
+
+/// Writes the contents of the ring buffer, oldest line first. `out` must not log.
+pub fn dmesg(out: &mut impl Write) -> fmt::Result {
+    without_interrupts(|| RING.lock().write_to(out))
+}
+
+/// Colour of log lines on the framebuffer, `None` keeps the console's default
+fn level_color(level: Level) -> Option<Color> {
+    match level {
+        Level::Error => Some(Color::RED),
+        Level::Warn => Some(Color::YELLOW),
+        Level::Info => None,
+        Level::Debug | Level::Trace => Some(Color::DARK_GRAY),
+    }
+}
+
+fn sink_enabled(sink: Sink, level: Level) -> bool {
+    level as usize <= SINK_LEVELS[sink as usize].load(Ordering::Relaxed)
+}
+
+struct Filters {
+    /// Level of targets without an override
+    default: LevelFilter,
+    targets: [Option<(&'static str, LevelFilter)>; MAX_TARGET_FILTERS],
+}
+
+impl Filters {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .flatten()
+            .filter(|(prefix, _)| target.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The `log` macros drop records above the max level before asking the logger, so it
+    /// has to be the highest level of any target
+    fn update_max_level(&self) {
+        let overrides = self.targets.iter().flatten().map(|(_, level)| *level);
+        log::set_max_level(overrides.fold(self.default, Ord::max));
+    }
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = without_interrupts(|| FILTERS.lock().level(metadata.target()));
+        metadata.level() <= level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let uptime = crate::time::uptime_ms();
+        let mut line = LineBuf::new();
+        let _ = write!(
+            line,
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            uptime / 1000,
+            uptime % 1000,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let line = line.finish();
+        let level = record.level();
+
+        without_interrupts(|| {
+            RING.lock().push(line.as_bytes());
+            if sink_enabled(Sink::Serial, level) {
+                crate::serial_print!("{}", line);
+            }
+            if sink_enabled(Sink::Framebuffer, level) {
+                match level_color(level) {
+                    Some(color) => crate::color_print!(color, "{}", line),
+                    None => crate::print!("{}", line),
+                }
+            }
+            for sink in EXTRA_SINKS.lock().iter_mut() {
+                sink.write(level, line);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Fixed size formatting buffer, so logging works without (and inside of) the allocator
+struct LineBuf {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl LineBuf {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_LINE],
+            len: 0,
+        }
+    }
+    /// Makes sure the line ends with a newline, even if it was truncated
+    fn finish(&mut self) -> &str {
+        if self.len == 0 || self.buf[self.len - 1] != b'\n' {
+            self.buf[self.len] = b'\n';
+            self.len += 1;
+        }
+        // only whole chars are ever copied in
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // keep room for the newline added by `finish`
+        let room = MAX_LINE - 1 - self.len;
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+struct Ring {
+    buf: [u8; RING_SIZE],
+    /// Next byte to write
+    head: usize,
+    /// Whether `buf` has been filled once, so the oldest data starts at `head`
+    wrapped: bool,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            buf: [0; RING_SIZE],
+            head: 0,
+            wrapped: false,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.buf[self.head] = byte;
+            self.head += 1;
+            if self.head == RING_SIZE {
+                self.head = 0;
+                self.wrapped = true;
+            }
+        }
+    }
+
+    fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        let (old, new) = self.buf.split_at(self.head);
+        let data = if self.wrapped {
+            // the oldest line was partially overwritten, skip it
+            let skip = new
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(new.len(), |i| i + 1);
+            [&new[skip..], old]
+        } else {
+            [&[][..], old]
+        };
+        let mut line = LineBuf::new();
+        for &byte in data.iter().flat_map(|part| part.iter()) {
+            if line.len < MAX_LINE {
+                line.buf[line.len] = byte;
+                line.len += 1;
+            }
+            if byte == b'\n' {
+                out.write_str(core::str::from_utf8(&line.buf[..line.len]).unwrap_or("?\n"))?;
+                line.len = 0;
+            }
+        }
+        Ok(())
+    }
+}
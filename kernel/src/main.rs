@@ -9,9 +9,8 @@ use bootloader_api::info::FrameBuffer;
 use bootloader_api::{entry_point, info::Optional, BootInfo};
 use conquer_once::spin::OnceCell;
 use core::panic::PanicInfo;
-use kernel::framebuffer::{FrameBufferWriter, FRAMEBUFFER};
-use kernel::{println, serial_println};
-mod wasm;
+use kernel::framebuffer::{Color, FrameBufferWriter, FRAMEBUFFER};
+use kernel::{color_println, println, serial_println};
 
 static LOGO: &str = r"
                    _     ___  ____  
@@ -26,11 +25,9 @@ entry_point!(kernel_main, config = &kernel::BOOTLOADER_CONFIG);
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     use kernel::allocator;
     use kernel::memory::{self, BootInfoFrameAllocator};
-    use kernel::task::{
-        executor::{Executor, Spawner},
-        keyboard,
-    };
+    use kernel::task::executor::{Executor, Spawner};
     use x86_64::VirtAddr;
+    kernel::ramdisk::init(boot_info);
     FRAMEBUFFER.init_once(|| {
         let frame = boot_info.framebuffer.as_mut();
         let info = match frame {
@@ -44,13 +41,25 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         spinning_top::Spinlock::new(FrameBufferWriter::new(buffer, info))
     });
     kernel::init();
+    let config = kernel::config::init();
     let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    kernel::gdt::init_stacks().expect("failed to map kernel stacks");
 
-    serial_println!("Still running");
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("Heap init failed!");
-    kernel::mouse::init();
+    log::info!("memory initialized");
+    allocator::init_heap().expect("Heap init failed!");
+    if let Some(keymap) = config.keymap {
+        if let Err(err) = kernel::keyboard::select(keymap) {
+            log::warn!("can't use keymap {}: {:?}", keymap, err);
+        }
+    }
+    kernel::framebuffer::enable_back_buffer().expect("failed to map the framebuffer back buffer");
+    if let Err(err) = kernel::mouse::init() {
+        log::warn!("no PS/2 mouse: {:?}", err);
+    }
+    let input_devices = kernel::virtio::input::probe();
     println!("{}", LOGO);
     println!("Welcome to yavko's WASM based tiny OS!");
 
@@ -59,10 +68,10 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let _result: anyhow::Result<()> = try {
         let spawner = Spawner::new(100);
         let mut executor = Executor::new(spawner.clone());
-        spawner.add(wasm::example_exec());
-        spawner.add(keyboard::print_keypresses());
-        spawner.add(kernel::task::mouse::process());
-        println!("Still running");
+        spawner
+            .add(kernel::service::init(spawner.clone(), input_devices))
+            .expect("the spawner is empty");
+        log::info!("starting executor");
         executor.run();
     };
 }
@@ -72,7 +81,9 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     serial_println!("Kernel {}", info);
-    println!("Kernel {}", info);
+    color_println!(Color::RED, "Kernel {}", info);
+    kernel::backtrace::print();
+    kernel::framebuffer::flush();
     kernel::hlt_loop();
 }
 
@@ -1,7 +1,11 @@
+pub mod vma;
+
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use conquer_once::spin::OnceCell;
+use spinning_top::Spinlock;
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
@@ -40,6 +44,9 @@ impl BootInfoFrameAllocator {
     }
 }
 
+// the memory map is never modified after boot
+unsafe impl Send for BootInfoFrameAllocator {}
+
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
         let frame = self.usable_frames().nth(self.next);
@@ -78,3 +85,56 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
+
+/// The active page table and frame allocator, once handed over with [`install`]
+static MEMORY: OnceCell<Spinlock<Memory>> = OnceCell::uninit();
+
+pub struct Memory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frames: BootInfoFrameAllocator,
+}
+
+impl Memory {
+    /// Returns where the given physical address is mapped in the kernel's address space
+    pub fn phys_to_virt(&self, addr: PhysAddr) -> VirtAddr {
+        self.mapper.phys_offset() + addr.as_u64()
+    }
+}
+
+/// Makes the page table and frame allocator available kernel wide, e.g. to the page fault
+/// handler. Must be called before the heap is initialized.
+pub fn install(mapper: OffsetPageTable<'static>, frames: BootInfoFrameAllocator) {
+    MEMORY.init_once(|| Spinlock::new(Memory { mapper, frames }));
+}
+
+/// Runs `f` with exclusive access to the page table and frame allocator
+pub fn with_memory<R>(f: impl FnOnce(&mut Memory) -> R) -> R {
+    let memory = MEMORY.get().expect("memory::install not called yet");
+    x86_64::instructions::interrupts::without_interrupts(|| f(&mut memory.lock()))
+}
+
+/// Allocates a zeroed frame for memory shared with a device, like a virtqueue.
+///
+/// Returns its physical address for the device and where the kernel can access it.
+pub fn allocate_dma_frame() -> Option<(PhysAddr, VirtAddr)> {
+    with_memory(|memory| {
+        let frame = memory.frames.allocate_frame()?;
+        let phys = frame.start_address();
+        let virt = memory.phys_to_virt(phys);
+        unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, frame.size() as usize) };
+        Some((phys, virt))
+    })
+}
+
+/// Whether `addr` is mapped in the active page table, `None` if that can't be told because
+/// memory isn't installed yet or its lock is held
+pub(crate) fn is_mapped(addr: VirtAddr) -> Option<bool> {
+    try_with_memory(|memory| memory.mapper.translate_addr(addr).is_some())
+}
+
+/// Like [`with_memory`], but gives up instead of spinning if the lock is held, for use in
+/// exception handlers
+pub(crate) fn try_with_memory<R>(f: impl FnOnce(&mut Memory) -> R) -> Option<R> {
+    let mut memory = MEMORY.get()?.try_lock()?;
+    Some(f(&mut memory))
+}
@@ -0,0 +1,274 @@
+//! Virtual memory areas: reserved ranges of the kernel's address space that are mapped on
+//! first touch by the page fault handler.
+use super::{try_with_memory, with_memory, Memory};
+use spinning_top::Spinlock;
+use x86_64::{
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB},
+    },
+    PhysAddr, VirtAddr,
+};
+
+const MAX_VMAS: usize = 64;
+const PAGE_SIZE: u64 = 4096;
+
+/// Where [`reserve`] places areas that don't need a fixed address
+pub(crate) const DYNAMIC_START: u64 = 0x_6000_0000_0000;
+const DYNAMIC_END: u64 = 0x_7000_0000_0000;
+
+static VMAS: Spinlock<[Option<Vma>; MAX_VMAS]> = Spinlock::new([None; MAX_VMAS]);
+static NEXT_DYNAMIC: Spinlock<u64> = Spinlock::new(DYNAMIC_START);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Zeroed frames, allocated when a page is first touched
+    Anonymous,
+    /// Never mapped, any access is an error. Used below stacks to catch overflows.
+    Guard,
+    /// Device memory, mapped up front by [`map_device`]
+    Device,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// Exclusive
+    pub end: VirtAddr,
+    /// Flags of pages mapped in this area, `PRESENT` is implied
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// Start or end isn't page aligned
+    Unaligned,
+    Overlap,
+    TooManyAreas,
+    OutOfAddressSpace,
+    /// No area starts at the given address
+    NotFound,
+    /// No frame was left to map
+    OutOfMemory,
+}
+
+/// How the page fault handler should deal with a fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultResolution {
+    /// The page was mapped, retry the access
+    Resolved,
+    /// The access hit a guard area
+    Guard(&'static str),
+    /// The area exists but doesn't allow the access, or no frame could be mapped
+    Violation(&'static str),
+    /// No area contains the address
+    Unmapped,
+}
+
+/// Registers an area; nothing is mapped until it's touched
+pub fn add(vma: Vma) -> Result<(), VmaError> {
+    if !vma.start.is_aligned(PAGE_SIZE) || !vma.end.is_aligned(PAGE_SIZE) || vma.end <= vma.start {
+        return Err(VmaError::Unaligned);
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vmas = VMAS.lock();
+        if vmas
+            .iter()
+            .flatten()
+            .any(|other| other.start < vma.end && vma.start < other.end)
+        {
+            return Err(VmaError::Overlap);
+        }
+        let slot = vmas
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(VmaError::TooManyAreas)?;
+        *slot = Some(vma);
+        Ok(())
+    })
+}
+
+/// Reserves `size` bytes (rounded up to pages) of anonymous memory at a free address,
+/// preceded by a guard page.
+pub fn reserve(name: &'static str, size: u64, flags: PageTableFlags) -> Result<VirtAddr, VmaError> {
+    reserve_area(name, size, flags, Backing::Anonymous)
+}
+
+fn reserve_area(
+    name: &'static str,
+    size: u64,
+    flags: PageTableFlags,
+    backing: Backing,
+) -> Result<VirtAddr, VmaError> {
+    let size = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let guard = {
+        let mut next = NEXT_DYNAMIC.lock();
+        let guard = *next;
+        if DYNAMIC_END - guard < size + PAGE_SIZE {
+            return Err(VmaError::OutOfAddressSpace);
+        }
+        *next += size + PAGE_SIZE;
+        VirtAddr::new(guard)
+    };
+    add(Vma {
+        name,
+        start: guard,
+        end: guard + PAGE_SIZE,
+        flags: PageTableFlags::empty(),
+        backing: Backing::Guard,
+    })?;
+    let start = guard + PAGE_SIZE;
+    add(Vma {
+        name,
+        start,
+        end: start + size,
+        flags,
+        backing,
+    })?;
+    Ok(start)
+}
+
+/// Maps `size` bytes of device registers at `phys` uncached, e.g. a PCI BAR.
+///
+/// The returned address has the same offset into its page as `phys`.
+pub fn map_device(name: &'static str, phys: PhysAddr, size: u64) -> Result<VirtAddr, VmaError> {
+    let offset = phys.as_u64() % PAGE_SIZE;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE;
+    let start = reserve_area(name, offset + size, flags, Backing::Device)?;
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let pages = (offset + size).div_ceil(PAGE_SIZE);
+    with_memory(|memory| {
+        let Memory { mapper, frames } = memory;
+        for i in 0..pages {
+            let page = Page::<Size4KiB>::containing_address(start + i * PAGE_SIZE);
+            let frame = first + i;
+            unsafe { mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, frames) }
+                .map_err(|_| VmaError::OutOfMemory)?
+                .flush();
+        }
+        Ok(start + offset)
+    })
+}
+
+/// Maps every page of the anonymous area starting at `start` right away, for memory that
+/// must never fault, like exception handler stacks.
+pub fn populate(start: VirtAddr) -> Result<(), VmaError> {
+    let vma = find(start)
+        .filter(|vma| vma.start == start && vma.backing == Backing::Anonymous)
+        .ok_or(VmaError::NotFound)?;
+    with_memory(|memory| {
+        let first = Page::<Size4KiB>::containing_address(vma.start);
+        let last = Page::<Size4KiB>::containing_address(vma.end - 1u64);
+        for page in Page::range_inclusive(first, last) {
+            if memory.mapper.translate_page(page).is_err() && !map_zeroed(memory, page, vma.flags) {
+                return Err(VmaError::OutOfMemory);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Returns the name of the guard area containing `addr`, for fault handlers
+pub(crate) fn guard_at(addr: VirtAddr) -> Option<&'static str> {
+    VMAS.try_lock()?
+        .iter()
+        .flatten()
+        .find(|vma| vma.backing == Backing::Guard && vma.contains(addr))
+        .map(|vma| vma.name)
+}
+
+/// Removes the area starting at `start` and unmaps its pages.
+///
+/// The frames are not returned to the frame allocator, as it can't take them back (yet).
+/// For device areas, `start` is the page aligned address.
+pub fn remove(start: VirtAddr) -> Option<Vma> {
+    let vma = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vmas = VMAS.lock();
+        let slot = vmas
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|v| v.start == start))?;
+        slot.take()
+    })?;
+    if vma.backing != Backing::Guard {
+        with_memory(|memory| {
+            let first = Page::<Size4KiB>::containing_address(vma.start);
+            let last = Page::<Size4KiB>::containing_address(vma.end - 1u64);
+            for page in Page::range_inclusive(first, last) {
+                if let Ok((_frame, flush)) = memory.mapper.unmap(page) {
+                    flush.flush();
+                }
+            }
+        });
+    }
+    Some(vma)
+}
+
+/// Returns the area containing `addr`
+pub fn find(addr: VirtAddr) -> Option<Vma> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        VMAS.lock()
+            .iter()
+            .flatten()
+            .find(|vma| vma.contains(addr))
+            .copied()
+    })
+}
+
+/// Called by the page fault handler, which must not spin on locks
+pub(crate) fn resolve_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> FaultResolution {
+    let vma = match VMAS.try_lock() {
+        Some(vmas) => vmas
+            .iter()
+            .flatten()
+            .find(|vma| vma.contains(addr))
+            .copied(),
+        None => None,
+    };
+    let Some(vma) = vma else {
+        return FaultResolution::Unmapped;
+    };
+    match vma.backing {
+        Backing::Guard => return FaultResolution::Guard(vma.name),
+        // mapped up front, there is nothing to fault in
+        Backing::Device => return FaultResolution::Violation(vma.name),
+        Backing::Anonymous => {}
+    }
+    let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        || (write && !vma.flags.contains(PageTableFlags::WRITABLE))
+    {
+        return FaultResolution::Violation(vma.name);
+    }
+    let page = Page::containing_address(addr);
+    match try_with_memory(|memory| map_zeroed(memory, page, vma.flags)) {
+        Some(true) => FaultResolution::Resolved,
+        _ => FaultResolution::Violation(vma.name),
+    }
+}
+
+fn map_zeroed(memory: &mut Memory, page: Page, flags: PageTableFlags) -> bool {
+    let Some(frame) = memory.frames.allocate_frame() else {
+        return false;
+    };
+    let frame_ptr: *mut u8 = memory.phys_to_virt(frame.start_address()).as_mut_ptr();
+    unsafe { core::ptr::write_bytes(frame_ptr, 0, PAGE_SIZE as usize) };
+    let Memory { mapper, frames } = memory;
+    match unsafe { mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, frames) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => false,
+    }
+}
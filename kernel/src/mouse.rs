@@ -1,124 +1,332 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
-
-use alloc::sync::Arc;
+//! PS/2 mouse driver.
+//!
+//! The interrupt handler only queues the raw bytes, the mouse task ([`crate::task::mouse`])
+//! decodes them into packets, and a [`Mouse`] moves the pointer and turns them into
+//! [`MouseEvent`]s for everyone who [`subscribe`]d. Other pointing devices, like virtio ones,
+//! report through a [`Mouse`] of their own.
+//!
+//! If the mouse supports it, the IntelliMouse extension is enabled for the scroll wheel.
+use crate::ps2::{self, controller_command, read_data, send_aux, write_config, Ps2Error};
+use crate::task::sync::broadcast::{self, Receiver, Sender};
 use conquer_once::spin::OnceCell;
-use crossbeam_utils::atomic::AtomicCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Controller configuration bit enabling IRQ 12
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+/// Controller configuration bit disabling the mouse clock
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// Device id of a mouse with a scroll wheel
+const INTELLIMOUSE_ID: u8 = 3;
+
+/// Number of events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 128;
+
+static WHEEL: AtomicBool = AtomicBool::new(false);
+static EVENTS: OnceCell<Sender<MouseEvent>> = OnceCell::uninit();
+static X: AtomicUsize = AtomicUsize::new(0);
+static Y: AtomicUsize = AtomicUsize::new(0);
 
-use crate::println;
-use ps2_mouse::{Mouse as MouseDevice, MouseState};
-use spinning_top::Spinlock;
+/// Enables the mouse, with scroll wheel if there is one, and its interrupt.
+///
+/// Interrupts are disabled meanwhile, as the answers of the mouse are polled.
+pub fn init() -> Result<(), Ps2Error> {
+    interrupts::without_interrupts(|| {
+        // leftovers, e.g. keys pressed during boot
+        while ps2::status() & ps2::STATUS_OUTPUT_FULL != 0 {
+            read_data()?;
+        }
+        controller_command(0xa8)?; // enable the aux port
+        controller_command(0x20)?; // read the configuration
+        let config = read_data()? & !CONFIG_AUX_CLOCK_DISABLED;
+        // no interrupts while the answers are polled
+        write_config(config & !CONFIG_AUX_INTERRUPT)?;
+
+        send_aux(0xf6)?; // defaults
+
+        // this sample rate sequence is the IntelliMouse knock, the id changes if it's understood
+        for rate in [200, 100, 80] {
+            send_aux(0xf3)?;
+            send_aux(rate)?;
+        }
+        send_aux(0xf2)?; // identify
+        let id = read_data()?;
+        WHEEL.store(id == INTELLIMOUSE_ID, Ordering::Relaxed);
+        send_aux(0xf4)?; // enable reporting
 
-static MOUSE: OnceCell<Mouse> = OnceCell::uninit();
+        write_config(config | CONFIG_AUX_INTERRUPT)
+    })?;
+    log::info!("mouse initialized, scroll wheel: {}", has_wheel());
+    show_pointer();
+    Ok(())
+}
 
-pub fn init() {
-    MOUSE.init_once(Mouse::default);
+/// Shows the pointer on the screen, once there is a pointing device
+pub(crate) fn show_pointer() {
+    interrupts::without_interrupts(|| {
+        if let Some(fb) = crate::framebuffer::FRAMEBUFFER.get() {
+            fb.lock().set_pointer_visible(true);
+        }
+    });
 }
 
-pub(crate) fn get() -> Option<Mouse> {
-    MOUSE.get().cloned()
+/// Whether the mouse sends scroll wheel movement
+pub fn has_wheel() -> bool {
+    WHEEL.load(Ordering::Relaxed)
 }
 
-#[derive(Clone)]
-pub(crate) struct Mouse {
-    dev: Arc<Spinlock<MouseDevice>>,
-    state: Arc<AtomicCell<Option<MouseState>>>,
-    x: Arc<AtomicUsize>,
-    y: Arc<AtomicUsize>,
+/// Position of the pointer in pixels
+pub fn position() -> (usize, usize) {
+    (X.load(Ordering::Relaxed), Y.load(Ordering::Relaxed))
 }
-impl Default for Mouse {
-    fn default() -> Self {
-        let mut cmd = x86_64::instructions::port::Port::<u8>::new(0x64);
-        let mut data = x86_64::instructions::port::Port::<u8>::new(0x60);
-        unsafe {
-            cmd.write(0xa8); // enable aux port
-            cmd.write(0x20); // read command byte
-            let mut status = data.read();
-            status |= 0b10; // enable aux port interrupts
-            cmd.write(0x60); // write command byte
-            data.write(status);
-            cmd.write(0xd4); // signal that next write is to mouse input buffer
-            data.write(0xf4); // enable mouse
-        }
 
-        let mut dev = MouseDevice::default();
-        dev.set_on_complete(Self::handler);
+fn events() -> &'static Sender<MouseEvent> {
+    EVENTS.get_or_init(|| broadcast::channel(EVENT_CAPACITY).0)
+}
 
-        Self {
-            dev: Arc::new(Spinlock::new(dev)),
-            state: Default::default(),
-            x: Default::default(),
-            y: Default::default(),
+/// Starts receiving mouse events. Slow receivers lose the oldest events.
+pub fn subscribe() -> Receiver<MouseEvent> {
+    events().subscribe()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    pub const ALL: [MouseButton; 3] = [Self::Left, Self::Right, Self::Middle];
+
+    /// Bit in the first packet byte and in [`Buttons`]
+    const fn mask(self) -> u8 {
+        match self {
+            Self::Left => 1 << 0,
+            Self::Right => 1 << 1,
+            Self::Middle => 1 << 2,
         }
     }
 }
-//static LAST_POS: Spinlock<(AtomicUsize, AtomicUsize)> =
-//    Spinlock::new((AtomicUsize::new(0), AtomicUsize::new(0)));
-impl Mouse {
-    fn handler(state: MouseState) {
-        let this: &Mouse = MOUSE.get().expect("mouse not initialized");
-        this.state.store(Some(state));
-
-        this.set_pos();
-
-        let x = this.x.load(Ordering::Relaxed);
-        let y = this.y.load(Ordering::Relaxed);
-        //let old = LAST_POS.lock();
-        // crate::framebuffer::FRAMEBUFFER
-        //     .get()
-        //     .unwrap()
-        //     .lock()
-        //     .write_pixel(
-        //         old.0.load(Ordering::Relaxed),
-        //         old.1.load(Ordering::Relaxed),
-        //         0,
-        //     );
-        crate::framebuffer::FRAMEBUFFER
-            .get()
-            .unwrap()
-            .lock()
-            .write_pixel(x, y, 255);
-        // LAST_POS.lock().0.store(x, Ordering::Relaxed);
-        // LAST_POS.lock().1.store(y, Ordering::Relaxed);
+
+/// The set of buttons held down
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub fn is_down(self, button: MouseButton) -> bool {
+        self.0 & button.mask() != 0
+    }
+
+    pub fn set(&mut self, button: MouseButton, down: bool) {
+        match down {
+            true => self.0 |= button.mask(),
+            false => self.0 &= !button.mask(),
+        }
     }
 
-    pub async fn add(&self, packet: u8) {
-        let mut dev = self.dev.lock();
-        dev.process_packet(packet);
+    /// One bit per button, in the order of [`MouseButton::ALL`]
+    pub fn bits(self) -> u8 {
+        self.0
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    /// How far the pointer moved in pixels, `dy` grows downwards like screen coordinates
+    Move {
+        dx: i16,
+        dy: i16,
+    },
+    ButtonDown(MouseButton),
+    ButtonUp(MouseButton),
+    /// Scroll wheel steps, positive when scrolled towards the user
+    Wheel(i8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// Pointer position after the event
+    pub x: usize,
+    pub y: usize,
+    /// Buttons held after the event
+    pub buttons: Buttons,
+    /// Uptime when the event was reported
+    pub time_ms: u64,
+}
 
-    fn set_pos(&self) {
-        let state = self.state.load().expect("mouse state not initialized");
-        let dx = state.get_x();
-        let dy = state.get_y();
+/// One decoded packet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    /// Movement, `dy` grows upwards as sent by the mouse
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
 
-        if dx > 0 {
-            self.x.fetch_add(dx as usize, Ordering::Relaxed);
-        } else {
-            self.x
-                .fetch_sub(dx.unsigned_abs() as usize, Ordering::Relaxed);
+/// Assembles packets from the bytes sent by the mouse, 3 bytes each or 4 with a scroll wheel
+pub struct Decoder {
+    bytes: [u8; 4],
+    len: usize,
+    size: usize,
+}
+
+/// Set in every first byte, used to find the start of a packet after losing bytes
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+impl Decoder {
+    pub const fn new(wheel: bool) -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+            size: if wheel { 4 } else { 3 },
         }
+    }
 
-        if dy > 0 {
-            self.y.fetch_sub(dy as usize, Ordering::Relaxed);
-        } else {
-            self.y
-                .fetch_add(dy.unsigned_abs() as usize, Ordering::Relaxed);
+    /// Adds a byte, returning the packet it completes
+    pub fn add(&mut self, byte: u8) -> Option<Packet> {
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            // out of sync, this can't be a first byte
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
         }
+        self.len = 0;
+        let [flags, x, y, wheel] = self.bytes;
+        // the deltas are 9 bit two's complement, with the sign bit in the first byte
+        let delta = |value: u8, sign: u8| match flags & sign {
+            0 => i16::from(value),
+            _ => i16::from(value) - 256,
+        };
+        // an overflowed movement is garbage
+        let overflow = flags & (X_OVERFLOW | Y_OVERFLOW) != 0;
+        Some(Packet {
+            dx: if overflow { 0 } else { delta(x, X_SIGN) },
+            dy: if overflow { 0 } else { delta(y, Y_SIGN) },
+            wheel: if self.size == 4 { wheel as i8 } else { 0 },
+            buttons: Buttons(flags & 0b111),
+        })
+    }
+}
 
-        self.limit_pos();
+/// Turns packets into pointer movement and [`MouseEvent`]s, one per pointing device
+pub struct Mouse {
+    buttons: Buttons,
+}
+
+impl Mouse {
+    pub const fn new() -> Self {
+        Self {
+            buttons: Buttons(0),
+        }
     }
 
-    fn limit_pos(&self) {
-        let x = self.x.load(Ordering::Relaxed);
-        let y = self.y.load(Ordering::Relaxed);
-        let (width, height) = crate::framebuffer::FRAMEBUFFER.get().unwrap().lock().size();
+    pub fn report(&mut self, packet: Packet) {
+        let time_ms = crate::time::uptime_ms();
+        if packet.dx != 0 || packet.dy != 0 {
+            let (x, y) = position();
+            // the mouse counts y upwards
+            let x = x.saturating_add_signed(packet.dx.into());
+            let y = y.saturating_add_signed((-packet.dy).into());
+            self.move_pointer(x, y, time_ms);
+        }
 
-        if x > width - 1 {
-            self.x.store(width - 1, Ordering::Relaxed);
+        for button in MouseButton::ALL {
+            let is = packet.buttons.is_down(button);
+            if self.buttons.is_down(button) != is {
+                self.buttons.set(button, is);
+                let kind = match is {
+                    true => MouseEventKind::ButtonDown(button),
+                    false => MouseEventKind::ButtonUp(button),
+                };
+                self.send(kind, time_ms);
+            }
         }
 
-        if y > height - 1 {
-            self.y.store(height - 1, Ordering::Relaxed);
+        if packet.wheel != 0 {
+            self.send(MouseEventKind::Wheel(packet.wheel), time_ms);
         }
     }
+
+    /// Moves the pointer to a position on the screen, for absolute pointing devices
+    pub fn move_to(&mut self, x: usize, y: usize) {
+        self.move_pointer(x, y, crate::time::uptime_ms());
+    }
+
+    fn move_pointer(&mut self, x: usize, y: usize, time_ms: u64) {
+        let (old_x, old_y) = position();
+        let (x, y) = interrupts::without_interrupts(|| {
+            let Some(fb) = crate::framebuffer::FRAMEBUFFER.get() else {
+                return (x, y);
+            };
+            let mut fb = fb.lock();
+            let (width, height) = fb.size();
+            let (x, y) = (x.min(width - 1), y.min(height - 1));
+            fb.move_pointer(x, y);
+            (x, y)
+        });
+        if (x, y) == (old_x, old_y) {
+            return;
+        }
+        X.store(x, Ordering::Relaxed);
+        Y.store(y, Ordering::Relaxed);
+        let delta = |new: usize, old: usize| (new as isize - old as isize) as i16;
+        let kind = MouseEventKind::Move {
+            dx: delta(x, old_x),
+            dy: delta(y, old_y),
+        };
+        self.send(kind, time_ms);
+    }
+
+    fn send(&self, kind: MouseEventKind, time_ms: u64) {
+        let (x, y) = position();
+        let event = MouseEvent {
+            kind,
+            x,
+            y,
+            buttons: self.buttons,
+            time_ms,
+        };
+        // nobody listening is fine
+        let _ = events().send(event);
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_decoder_signs_and_resync() {
+    let mut decoder = Decoder::new(false);
+    // a stray byte without the always-one bit is skipped
+    assert_eq!(decoder.add(0x00), None);
+    assert_eq!(decoder.add(ALWAYS_ONE | X_SIGN | 0b001), None);
+    assert_eq!(decoder.add(0xff), None);
+    let packet = decoder.add(0x05).unwrap();
+    assert_eq!((packet.dx, packet.dy), (-1, 5));
+    assert!(packet.buttons.is_down(MouseButton::Left));
+    assert!(!packet.buttons.is_down(MouseButton::Right));
+}
+
+#[test_case]
+fn test_decoder_wheel() {
+    let mut decoder = Decoder::new(true);
+    for byte in [ALWAYS_ONE | 0b100, 0, 0] {
+        assert_eq!(decoder.add(byte), None);
+    }
+    let packet = decoder.add(0xff).unwrap();
+    assert_eq!(packet.wheel, -1);
+    assert!(packet.buttons.is_down(MouseButton::Middle));
 }
@@ -0,0 +1,200 @@
+//! PCI configuration space access through the legacy I/O ports, and device enumeration
+use spinning_top::Spinlock;
+use x86_64::instructions::{interrupts, port::Port};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Held while an address is written and the data accessed, so accesses don't interleave
+static CONFIG_LOCK: Spinlock<()> = Spinlock::new(());
+
+const VENDOR_NONE: u16 = 0xffff;
+const OFFSET_COMMAND: u8 = 0x04;
+const OFFSET_STATUS: u8 = 0x06;
+const OFFSET_HEADER_TYPE: u8 = 0x0e;
+const OFFSET_BAR0: u8 = 0x10;
+const OFFSET_CAPABILITIES: u8 = 0x34;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+
+/// Location of a function on the PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        1 << 31
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xfc)
+    }
+
+    /// Reads the aligned dword containing `offset`
+    pub fn read(self, offset: u8) -> u32 {
+        interrupts::without_interrupts(|| {
+            let _lock = CONFIG_LOCK.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::new(CONFIG_DATA).read()
+            }
+        })
+    }
+
+    /// Writes the aligned dword containing `offset`
+    pub fn write(self, offset: u8, value: u32) {
+        interrupts::without_interrupts(|| {
+            let _lock = CONFIG_LOCK.lock();
+            unsafe {
+                Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::new(CONFIG_DATA).write(value);
+            }
+        })
+    }
+
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, prefetchable: bool },
+    Io { port: u16 },
+}
+
+/// An entry of a function's capability list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Where it is in configuration space
+    pub offset: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+}
+
+impl PciDevice {
+    fn probe(address: PciAddress) -> Option<Self> {
+        let id = address.read(0x00);
+        if id as u16 == VENDOR_NONE {
+            return None;
+        }
+        let class = address.read(0x08);
+        Some(Self {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+        })
+    }
+
+    /// Decodes base address register `index` (0 to 5), `None` if it's unused
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        let offset = OFFSET_BAR0 + 4 * index;
+        let low = self.address.read(offset);
+        if low & 1 == 1 {
+            let port = (low & !0b11) as u16;
+            return (port != 0).then_some(Bar::Io { port });
+        }
+        let mut address = u64::from(low & !0b1111);
+        // type 2 is a 64 bit BAR, taking up the next register as well
+        if (low >> 1) & 0b11 == 2 && index < 5 {
+            address |= u64::from(self.address.read(offset + 4)) << 32;
+        }
+        (address != 0).then_some(Bar::Memory {
+            address,
+            prefetchable: low & 0b1000 != 0,
+        })
+    }
+
+    /// Walks the capability list
+    pub fn capabilities(&self) -> impl Iterator<Item = Capability> {
+        let address = self.address;
+        let mut next = match address.read_u16(OFFSET_STATUS) & STATUS_CAPABILITIES {
+            0 => 0,
+            _ => address.read_u8(OFFSET_CAPABILITIES) & !0b11,
+        };
+        // bounded, a broken list could loop
+        (0..48).map_while(move |_| {
+            if next == 0 {
+                return None;
+            }
+            let offset = next;
+            next = address.read_u8(offset + 1) & !0b11;
+            Some(Capability {
+                id: address.read_u8(offset),
+                offset,
+            })
+        })
+    }
+
+    /// Lets the device decode its memory BARs and access memory itself
+    pub fn enable_bus_master(&self) {
+        let command = self.address.read_u16(OFFSET_COMMAND);
+        // the upper half is the status register, whose bits are cleared by writing ones
+        let command = command | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER;
+        self.address.write(OFFSET_COMMAND, u32::from(command));
+    }
+}
+
+/// Scans every bus for devices, in order of their address
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255u8).flat_map(|bus| {
+        (0..32u8).flat_map(move |device| {
+            let first = PciDevice::probe(PciAddress::new(bus, device, 0));
+            let functions = match first {
+                Some(first)
+                    if first.address.read_u8(OFFSET_HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 =>
+                {
+                    8
+                }
+                Some(_) => 1,
+                None => 0,
+            };
+            (0..functions).filter_map(move |function| {
+                PciDevice::probe(PciAddress::new(bus, device, function))
+            })
+        })
+    })
+}
+
+#[test_case]
+fn test_host_bridge() {
+    // QEMU always puts the host bridge first
+    let bridge = devices().next().expect("no PCI devices");
+    assert_eq!(bridge.address, PciAddress::new(0, 0, 0));
+    assert_eq!((bridge.class, bridge.subclass), (0x06, 0x00));
+}
@@ -0,0 +1,111 @@
+//! The PS/2 controller, which the keyboard and the mouse are attached to.
+//!
+//! Everything here polls the controller, so it's only meant for commands sent with
+//! interrupts disabled, before an interrupt handler takes the answer away.
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, commands to the controller when written
+const COMMAND_PORT: u16 = 0x64;
+pub(crate) const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The byte in the output buffer came from the mouse
+const STATUS_AUX_DATA: u8 = 1 << 5;
+/// Number of status polls before giving up on the controller
+const TIMEOUT: usize = 100_000;
+/// Acknowledgement of a command, also seen by the keyboard interrupt handler
+pub(crate) const ACK: u8 = 0xfa;
+/// The device asks for the last byte again
+pub(crate) const RESEND: u8 = 0xfe;
+/// Attempts at a byte the device keeps asking to resend
+const RETRIES: usize = 3;
+/// Bytes of the other device passed on while waiting for a reply, e.g. mouse movement
+const MAX_OTHER_BYTES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The controller or device didn't respond
+    Timeout,
+    /// The device answered a command with something other than an acknowledgement
+    Rejected { command: u8, response: u8 },
+}
+
+pub(crate) fn status() -> u8 {
+    unsafe { Port::<u8>::new(COMMAND_PORT).read() }
+}
+
+fn wait_for(bit: u8, set: bool) -> Result<(), Ps2Error> {
+    (0..TIMEOUT)
+        .any(|_| (status() & bit != 0) == set)
+        .then_some(())
+        .ok_or(Ps2Error::Timeout)
+}
+
+pub(crate) fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(STATUS_OUTPUT_FULL, true)?;
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+/// Reads a byte sent by the keyboard, or the mouse if `aux` is set. Bytes of the other device
+/// go to its interrupt stream, as its handler would have taken them.
+fn read_reply(aux: bool) -> Result<u8, Ps2Error> {
+    for _ in 0..MAX_OTHER_BYTES {
+        wait_for(STATUS_OUTPUT_FULL, true)?;
+        let from_aux = status() & STATUS_AUX_DATA != 0;
+        let byte = unsafe { Port::<u8>::new(DATA_PORT).read() };
+        match (from_aux, aux) {
+            (true, true) | (false, false) => return Ok(byte),
+            (true, false) => crate::task::mouse::PACKETS.push(byte),
+            (false, true) => crate::task::keyboard::SCANCODES.push(byte),
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+pub(crate) fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+pub(crate) fn controller_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+pub(crate) fn write_config(config: u8) -> Result<(), Ps2Error> {
+    controller_command(0x60)?;
+    write_data(config)
+}
+
+/// Sends a byte to the keyboard and waits for the acknowledgement
+pub(crate) fn send_keyboard(byte: u8) -> Result<(), Ps2Error> {
+    send(byte, false)
+}
+
+/// Sends a byte to the mouse and waits for the acknowledgement
+pub(crate) fn send_aux(byte: u8) -> Result<(), Ps2Error> {
+    send(byte, true)
+}
+
+fn send(byte: u8, aux: bool) -> Result<(), Ps2Error> {
+    let mut response = RESEND;
+    for _ in 0..RETRIES {
+        if aux {
+            controller_command(0xd4)?; // the next data byte goes to the aux port
+        }
+        write_data(byte)?;
+        response = read_reply(aux)?;
+        if response != RESEND {
+            break;
+        }
+    }
+    match response {
+        ACK => Ok(()),
+        response => Err(Ps2Error::Rejected {
+            command: byte,
+            response,
+        }),
+    }
+}
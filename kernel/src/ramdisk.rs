@@ -0,0 +1,85 @@
+//! Read-only access to the ramdisk the bootloader loads for us.
+//!
+//! The ramdisk is a ustar archive, built by the `yos` build script. Files are returned as
+//! slices of it, so no allocation is needed and it can be used very early during boot.
+use bootloader_api::BootInfo;
+use conquer_once::spin::OnceCell;
+
+const BLOCK_SIZE: usize = 512;
+
+static RAMDISK: OnceCell<&'static [u8]> = OnceCell::uninit();
+
+/// Remembers where the bootloader put the ramdisk, if there is one
+pub fn init(boot_info: &BootInfo) {
+    if let Some(&addr) = boot_info.ramdisk_addr.as_ref() {
+        let len = boot_info.ramdisk_len as usize;
+        RAMDISK.init_once(|| unsafe { core::slice::from_raw_parts(addr as *const u8, len) });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct File {
+    /// Path inside the archive, without a leading `./` or `/`
+    pub path: &'static str,
+    pub data: &'static [u8],
+}
+
+/// Iterates over all regular files on the ramdisk
+pub fn files() -> impl Iterator<Item = File> {
+    Entries {
+        archive: RAMDISK.get().copied().unwrap_or_default(),
+        offset: 0,
+    }
+}
+
+/// Looks up a file by path, a leading `/` is ignored.
+///
+/// If the archive has the path more than once the last one counts, like when it's extracted,
+/// so files appended by the runner replace the built-in ones.
+pub fn get(path: &str) -> Option<&'static [u8]> {
+    let path = path.trim_start_matches('/');
+    files()
+        .filter(|file| file.path == path)
+        .last()
+        .map(|file| file.data)
+}
+
+struct Entries {
+    archive: &'static [u8],
+    offset: usize,
+}
+
+impl Iterator for Entries {
+    type Item = File;
+
+    fn next(&mut self) -> Option<File> {
+        loop {
+            let header = self.archive.get(self.offset..self.offset + BLOCK_SIZE)?;
+            let name = cstr(&header[..100]);
+            if name.is_empty() {
+                // end of archive
+                return None;
+            }
+            let size = octal(&header[124..136])?;
+            let data_start = self.offset + BLOCK_SIZE;
+            let data = self.archive.get(data_start..data_start + size)?;
+            self.offset = data_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            // '0' (or NUL in old archives) are regular files, skip directories and links
+            if matches!(header[156], b'0' | 0) {
+                let path = name.trim_start_matches("./").trim_start_matches('/');
+                return Some(File { path, data });
+            }
+        }
+    }
+}
+
+/// Header fields are NUL terminated, unless they fill the whole field
+fn cstr(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or_default()
+}
+
+fn octal(field: &[u8]) -> Option<usize> {
+    usize::from_str_radix(cstr(field).trim(), 8).ok()
+}
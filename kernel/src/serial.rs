@@ -1,15 +1,40 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::PortReadOnly;
+
+const COM1: u16 = 0x3F8;
+/// Line status register, bit 0 is set while a received byte waits in the data register
+const LINE_STATUS: u16 = COM1 + 5;
+const DATA_READY: u8 = 1 << 0;
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        // also enables the interrupt for received bytes
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
         Mutex::new(serial_port)
     };
 }
 
+/// Sets up COM1, before its interrupt is unmasked
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+}
+
+/// Moves the received bytes into [`crate::task::serial::INPUT`], called by the COM1
+/// interrupt handler.
+///
+/// Reads the ports directly, so it never waits for [`SERIAL1`] held by a writer.
+pub(crate) fn receive() {
+    let mut status = PortReadOnly::<u8>::new(LINE_STATUS);
+    let mut data = PortReadOnly::<u8>::new(COM1);
+    // the FIFO may hold several bytes by the time the interrupt is handled
+    while unsafe { status.read() } & DATA_READY != 0 {
+        crate::task::serial::INPUT.push(unsafe { data.read() });
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
@@ -0,0 +1,389 @@
+//! The init task: starts the drivers, then runs the WASM services listed in the manifest at
+//! [`MANIFEST_PATH`] on the ramdisk and restarts them when they fail.
+//!
+//! The manifest has one section per service, `#` starts a comment:
+//!
+//! ```text
+//! [clock]
+//! program = /bin/clock.wasm
+//! args = --utc --seconds
+//! env = TZ=UTC LANG=C
+//! caps = console dir:/etc port:clock
+//! restart = on-failure
+//! max-restarts = 5
+//! ```
+//!
+//! Only `program` is required, see [`ServiceConfig`] for the others. A service fails when it
+//! can't be loaded, imports host functions its capabilities don't allow, returns an error or
+//! traps. Without a manifest a single service named `init` runs the `init=` program of the
+//! [boot config](crate::config), or the built-in example, with
+//! [every capability](wasm::capability::ALL).
+use crate::task::executor::{SpawnError, Spawner};
+use crate::task::{Exit, Priority, TaskId};
+use crate::virtio::input::InputDevice;
+use crate::wasm::{self, Capabilities};
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::cell::Cell;
+use spinning_top::Spinlock;
+
+/// Path of the service manifest on the ramdisk
+pub const MANIFEST_PATH: &str = "etc/services";
+/// Program path that stands for [`wasm::EXAMPLE`]
+pub const EXAMPLE_PATH: &str = "builtin:example";
+/// Wait before the first restart, doubled for every further one up to [`MAX_RESTART_DELAY_MS`]
+const RESTART_DELAY_MS: u64 = 500;
+const MAX_RESTART_DELAY_MS: u64 = 16_000;
+
+static SERVICES: Spinlock<Vec<ServiceStatus>> = Spinlock::new(Vec::new());
+
+/// When a service is started again after it ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// `always`: also when it finished successfully
+    Always,
+    /// `on-failure`
+    OnFailure,
+    /// `never`
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceConfig {
+    /// The section name
+    pub name: &'static str,
+    /// `program`: path of the WASM program on the ramdisk, or [`EXAMPLE_PATH`]
+    pub program: &'static str,
+    /// `args`: arguments separated by whitespace, passed after the program path
+    pub args: &'static str,
+    /// `env`: `KEY=VALUE` pairs separated by whitespace
+    pub env: &'static str,
+    /// `caps`: the [capabilities](wasm::capability) it gets, none by default
+    pub caps: &'static str,
+    /// `restart`: `always`, `on-failure` (the default) or `never`
+    pub restart: Restart,
+    /// `max-restarts`: how often it's restarted before giving up, 5 by default
+    pub max_restarts: u32,
+}
+
+impl ServiceConfig {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            program: "",
+            args: "",
+            env: "",
+            caps: "",
+            restart: Restart::OnFailure,
+            max_restarts: 5,
+        }
+    }
+
+    /// The arguments the program gets, starting with its path
+    pub fn argv(&self) -> impl Iterator<Item = &'static str> {
+        core::iter::once(self.program).chain(self.args.split_whitespace())
+    }
+
+    /// The environment variables the program gets
+    pub fn vars(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        self.env
+            .split_whitespace()
+            .map(|var| var.split_once('=').unwrap_or((var, "")))
+    }
+
+    /// Sets an option from a `key = value` line, returning whether it was valid
+    fn set(&mut self, line: &'static str) -> bool {
+        let Some((key, value)) = line.split_once('=') else {
+            return false;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "program" => self.program = value,
+            "args" => self.args = value,
+            "env" => self.env = value,
+            "caps" => self.caps = value,
+            "restart" => {
+                self.restart = match value {
+                    "always" => Restart::Always,
+                    "on-failure" => Restart::OnFailure,
+                    "never" => Restart::Never,
+                    _ => return false,
+                }
+            }
+            "max-restarts" => match value.parse() {
+                Ok(max) => self.max_restarts = max,
+                Err(_) => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Parses a manifest into the services it lists, warning about and skipping what it doesn't
+/// understand
+pub fn parse(manifest: &'static str) -> Manifest {
+    Manifest {
+        lines: manifest.lines(),
+        section: None,
+    }
+}
+
+/// Iterator returned by [`parse`]
+pub struct Manifest {
+    lines: core::str::Lines<'static>,
+    /// Name of the section whose header was read already
+    section: Option<&'static str>,
+}
+
+impl Manifest {
+    /// The next line that isn't empty or a comment
+    fn next_line(&mut self) -> Option<&'static str> {
+        self.lines
+            .by_ref()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .find(|line| !line.is_empty())
+    }
+}
+
+/// Returns the name if the line is a `[name]` section header
+fn section_name(line: &'static str) -> Option<&'static str> {
+    line.strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+impl Iterator for Manifest {
+    type Item = ServiceConfig;
+
+    fn next(&mut self) -> Option<ServiceConfig> {
+        loop {
+            let name = match self.section.take() {
+                Some(name) => name,
+                None => loop {
+                    let line = self.next_line()?;
+                    match section_name(line) {
+                        Some(name) => break name,
+                        None => log::warn!("services: {line:?} is outside of a service"),
+                    }
+                },
+            };
+            let mut service = ServiceConfig::new(name);
+            while let Some(line) = self.next_line() {
+                if let Some(name) = section_name(line) {
+                    self.section = Some(name);
+                    break;
+                }
+                if !service.set(line) {
+                    log::warn!("service {name}: bad option {line:?}");
+                }
+            }
+            if !service.program.is_empty() {
+                return Some(service);
+            }
+            log::warn!("service {name} has no program");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    /// Waiting to be restarted
+    Restarting,
+    /// Ended and not restarted
+    Exited,
+    /// Couldn't be loaded or started, was killed, or failed more than `max-restarts` times
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceStatus {
+    pub name: &'static str,
+    pub state: State,
+    /// The task running it, while it's [`State::Running`]
+    pub task: Option<TaskId>,
+    pub restarts: u32,
+}
+
+/// Returns a snapshot of the status of all services, in the order of the manifest
+pub fn status() -> Vec<ServiceStatus> {
+    SERVICES.lock().clone()
+}
+
+/// Starts the drivers, the serial shell and the compositor, then supervises the services
+pub async fn init(spawner: Spawner, input_devices: Vec<InputDevice>) {
+    log_spawn_error(
+        "keyboard driver",
+        spawner.add_with_priority(Priority::Input, crate::task::keyboard::process()),
+    );
+    log_spawn_error(
+        "mouse driver",
+        spawner.add_with_priority(Priority::Input, crate::task::mouse::process()),
+    );
+    for device in input_devices {
+        log_spawn_error(
+            "input device",
+            spawner.add_with_priority(Priority::Input, device.run()),
+        );
+    }
+    log_spawn_error("serial shell", spawner.add(crate::shell::serial()));
+    log_spawn_error("compositor", spawner.add(crate::framebuffer::compositor()));
+    log_spawn_error("cursor", spawner.add(crate::framebuffer::blink_cursor()));
+
+    let manifest = crate::ramdisk::get(MANIFEST_PATH).map(core::str::from_utf8);
+    let services: Vec<_> = match manifest {
+        Some(Ok(manifest)) => parse(manifest).collect(),
+        Some(Err(_)) => {
+            log::error!("{MANIFEST_PATH} is not UTF-8");
+            Vec::new()
+        }
+        None => {
+            let mut service = ServiceConfig::new("init");
+            service.program = crate::config::get().init.unwrap_or(EXAMPLE_PATH);
+            service.caps = wasm::capability::ALL;
+            service.restart = Restart::Never;
+            Vec::from([service])
+        }
+    };
+    SERVICES
+        .lock()
+        .extend(services.iter().map(|service| ServiceStatus {
+            name: service.name,
+            state: State::Restarting,
+            task: None,
+            restarts: 0,
+        }));
+    for (index, service) in services.into_iter().enumerate() {
+        let name = service.name;
+        if spawner
+            .add(supervise(spawner.clone(), index, service))
+            .is_err()
+        {
+            log::error!("service {name}: too many tasks to supervise it");
+            set_state(index, State::Failed, None);
+        }
+    }
+}
+
+/// The kernel runs on without tasks that couldn't be started, but says so
+fn log_spawn_error(task: &str, result: Result<(), SpawnError>) {
+    if result.is_err() {
+        log::error!("too many tasks to start the {task}");
+    }
+}
+
+/// Runs a service until it ends for good
+async fn supervise(spawner: Spawner, index: usize, service: ServiceConfig) {
+    let name = service.name;
+    let program = match service.program {
+        EXAMPLE_PATH => Some(wasm::EXAMPLE),
+        path => crate::ramdisk::get(path),
+    };
+    let Some(program) = program else {
+        log::error!("service {name}: program {} not found", service.program);
+        set_state(index, State::Failed, None);
+        return;
+    };
+    let caps = match Capabilities::parse(service.caps) {
+        Ok(caps) => caps,
+        Err(err) => {
+            log::error!("service {name}: {err:?}");
+            set_state(index, State::Failed, None);
+            return;
+        }
+    };
+    let mut restarts = 0;
+    loop {
+        // stays set unless the program returns successfully
+        let failed = Rc::new(Cell::new(true));
+        let result = Rc::clone(&failed);
+        let args = service.argv().map(String::from).collect();
+        let env = service
+            .vars()
+            .map(|(key, value)| (String::from(key), String::from(value)))
+            .collect();
+        let (caps, child_spawner) = (caps.clone(), spawner.clone());
+        let process = spawner.spawn_process(async move {
+            match wasm::run(program, args, env, caps, child_spawner).await {
+                Ok(()) => result.set(false),
+                Err(err) => log::error!("service {name}: {err:?}"),
+            }
+        });
+        let Ok(process) = process else {
+            log::error!("service {name}: too many tasks to start it");
+            set_state(index, State::Failed, None);
+            return;
+        };
+        log::info!("service {name} started as task {}", process.id().as_u64());
+        set_state(index, State::Running, Some(process.id()));
+        if process.wait().await == Exit::Killed {
+            // whatever the process held may not have been released, so it's not restarted
+            log::error!("service {name} was killed");
+            set_state(index, State::Failed, None);
+            return;
+        }
+        let failed = failed.get();
+        let reason = if failed { "failed" } else { "exited" };
+        let restart = match service.restart {
+            Restart::Always => true,
+            Restart::OnFailure => failed,
+            Restart::Never => false,
+        };
+        if !restart {
+            log::info!("service {name} {reason}");
+            let state = if failed { State::Failed } else { State::Exited };
+            set_state(index, state, None);
+            return;
+        }
+        if restarts >= service.max_restarts {
+            log::error!("service {name} {reason}, giving up after {restarts} restarts");
+            set_state(index, State::Failed, None);
+            return;
+        }
+        let delay = (RESTART_DELAY_MS << restarts.min(8)).min(MAX_RESTART_DELAY_MS);
+        log::warn!("service {name} {reason}, restarting in {delay} ms");
+        restarts += 1;
+        if let Some(status) = SERVICES.lock().get_mut(index) {
+            status.restarts = restarts;
+        }
+        set_state(index, State::Restarting, None);
+        crate::time::sleep_ms(delay).await;
+    }
+}
+
+fn set_state(index: usize, state: State, task: Option<TaskId>) {
+    if let Some(status) = SERVICES.lock().get_mut(index) {
+        status.state = state;
+        status.task = task;
+    }
+}
+
+#[test_case]
+fn test_parse_manifest() {
+    let manifest = "# services\n[clock]\nprogram = /bin/clock.wasm # the clock\nargs = --utc -s\n\
+                    env = TZ=UTC LANG\ncaps = console ipc\nrestart = always\nmax-restarts = 2\n\n\
+                    [ shell ]\nprogram=/bin/shell.wasm\n";
+    let mut services = parse(manifest);
+    let clock = services.next().unwrap();
+    assert_eq!(clock.name, "clock");
+    assert_eq!(clock.program, "/bin/clock.wasm");
+    assert!(clock.argv().eq(["/bin/clock.wasm", "--utc", "-s"]));
+    assert!(clock.vars().eq([("TZ", "UTC"), ("LANG", "")]));
+    assert_eq!(clock.caps, "console ipc");
+    assert_eq!(clock.restart, Restart::Always);
+    assert_eq!(clock.max_restarts, 2);
+    let shell = services.next().unwrap();
+    assert_eq!(shell.name, "shell");
+    assert_eq!(shell.restart, Restart::OnFailure);
+    assert_eq!(shell.caps, "");
+    assert!(services.next().is_none());
+}
+
+#[test_case]
+fn test_parse_bad_manifest() {
+    // options before the first section and services without a program are skipped
+    let manifest = "program = /a.wasm\n[a]\nrestart = sometimes\n[b]\nprogram = /b.wasm\nfoo\n";
+    let mut services = parse(manifest);
+    let b = services.next().unwrap();
+    assert_eq!(b.name, "b");
+    assert_eq!(b.restart, Restart::OnFailure);
+    assert!(services.next().is_none());
+}
@@ -0,0 +1,245 @@
+//! A small command shell on the serial port, so the OS can be driven headlessly from a host
+//! terminal or a test script.
+//!
+//! Lines are read from [`INPUT`] and echoed back, backspace is the only editing key. Both
+//! `\r` and `\n` end a line, so it works from raw terminals as well as piped input.
+//!
+//! The kernel log goes to COM1 as well, until the first input arrives. From then on it would
+//! mix with the shell's output, so it's only kept for `dmesg`.
+use crate::logger::{self, Sink};
+use crate::service::{self, State};
+use crate::task::{executor, serial::INPUT, Priority};
+use core::fmt::{self, Write};
+use futures_util::StreamExt;
+
+const MAX_LINE: usize = 256;
+/// Distinct enough that log lines and command output don't look like it
+const PROMPT: &str = "yos> ";
+
+type Command = fn(&str, &mut dyn Write) -> fmt::Result;
+
+/// Name, arguments and description, and what runs it
+const COMMANDS: [(&str, &str, Command); 8] = [
+    ("help", "list the commands", help),
+    ("echo", "TEXT  print the text", echo),
+    ("uptime", "show the time since boot", uptime),
+    ("dmesg", "show the kernel log", dmesg),
+    ("top", "show the tasks and where the time went", top),
+    ("services", "show the services started by init", services),
+    (
+        "layout",
+        "[NAME]  show or select the keyboard layout",
+        layout,
+    ),
+    ("poweroff", "exit QEMU", poweroff),
+];
+
+/// Writes to COM1
+struct SerialWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::serial::_print(format_args!("{s}"));
+        Ok(())
+    }
+}
+
+/// Runs the shell on COM1
+pub async fn serial() {
+    let mut input = INPUT.subscribe();
+    let mut out = SerialWriter;
+    let mut editor = LineEditor::new();
+    let _ = write!(out, "{PROMPT}");
+    let mut attached = false;
+    while let Some(byte) = input.next().await {
+        if !attached {
+            log::info!("serial shell in use, see dmesg for the kernel log");
+            logger::set_sink_level(Sink::Serial, log::LevelFilter::Off);
+            attached = true;
+        }
+        if editor.feed(byte, &mut out) {
+            let _ = run(editor.line(), &mut out);
+            editor.clear();
+            let _ = write!(out, "{PROMPT}");
+        }
+    }
+}
+
+/// Runs a command line
+pub fn run(line: &str, out: &mut dyn Write) -> fmt::Result {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(());
+    }
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    match COMMANDS.iter().find(|(command, ..)| *command == name) {
+        Some((_, _, command)) => command(args.trim(), out),
+        None => writeln!(out, "{name}: unknown command, try help"),
+    }
+}
+
+fn help(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    for (name, description, _) in COMMANDS {
+        writeln!(out, "  {name} {description}")?;
+    }
+    Ok(())
+}
+
+fn echo(args: &str, out: &mut dyn Write) -> fmt::Result {
+    writeln!(out, "{args}")
+}
+
+fn uptime(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    let ms = crate::time::uptime_ms();
+    writeln!(out, "up {}.{:03} s", ms / 1000, ms % 1000)
+}
+
+fn dmesg(_args: &str, mut out: &mut dyn Write) -> fmt::Result {
+    crate::logger::dmesg(&mut out)
+}
+
+fn top(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    let stats = executor::stats();
+    let busy: u64 = stats.iter().map(|task| task.cycles).sum();
+    let idle = executor::idle_cycles();
+    let percent = |cycles: u64| cycles * 100 / (busy + idle).max(1);
+    writeln!(
+        out,
+        "{:>4} {:<12} {:>10} {:>14} {:>4}  NAME",
+        "ID", "PRIORITY", "POLLS", "CYCLES", "%"
+    )?;
+    for task in stats {
+        let priority = match task.priority {
+            Priority::Input => "input",
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+        };
+        writeln!(
+            out,
+            "{:>4} {:<12} {:>10} {:>14} {:>4}  {}",
+            task.id.as_u64(),
+            priority,
+            task.polls,
+            task.cycles,
+            percent(task.cycles),
+            task.name
+        )?;
+    }
+    writeln!(out, "idle {}%", percent(idle))
+}
+
+fn services(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    writeln!(out, "{:<12} {:>4} {:>8}  NAME", "STATE", "TASK", "RESTARTS")?;
+    for service in service::status() {
+        let state = match service.state {
+            State::Running => "running",
+            State::Restarting => "restarting",
+            State::Exited => "exited",
+            State::Failed => "failed",
+        };
+        match service.task {
+            Some(task) => write!(out, "{:<12} {:>4}", state, task.as_u64())?,
+            None => write!(out, "{:<12} {:>4}", state, "-")?,
+        }
+        writeln!(out, " {:>8}  {}", service.restarts, service.name)?;
+    }
+    Ok(())
+}
+
+fn layout(args: &str, out: &mut dyn Write) -> fmt::Result {
+    if args.is_empty() {
+        return writeln!(out, "{}", crate::keyboard::layout().name());
+    }
+    match crate::keyboard::select(args) {
+        Ok(()) => Ok(()),
+        Err(err) => writeln!(out, "layout {args}: {err:?}"),
+    }
+}
+
+fn poweroff(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    writeln!(out, "bye")?;
+    crate::exit_qemu(crate::QemuExitCode::Success);
+    // not running in QEMU
+    crate::hlt_loop();
+}
+
+/// Collects a line of printable ASCII, echoing what it takes
+struct LineEditor {
+    bytes: [u8; MAX_LINE],
+    len: usize,
+    /// The last byte ended a line with `\r`, a `\n` right after it belongs to it
+    after_cr: bool,
+}
+
+impl LineEditor {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; MAX_LINE],
+            len: 0,
+            after_cr: false,
+        }
+    }
+
+    /// Adds a byte, returning whether it ended the line
+    fn feed(&mut self, byte: u8, echo: &mut dyn Write) -> bool {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => false,
+            b'\r' | b'\n' => {
+                let _ = echo.write_str("\r\n");
+                true
+            }
+            // backspace and delete, terminals send either
+            0x08 | 0x7f if self.len > 0 => {
+                self.len -= 1;
+                let _ = echo.write_str("\x08 \x08");
+                false
+            }
+            0x20..=0x7e if self.len < MAX_LINE => {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+                let _ = echo.write_char(byte as char);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn line(&self) -> &str {
+        // only ASCII is taken
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+struct Discard;
+
+#[cfg(test)]
+impl Write for Discard {
+    fn write_str(&mut self, _s: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_line_editor() {
+    let mut editor = LineEditor::new();
+    let mut ended = [false; 8];
+    for (ended, byte) in ended.iter_mut().zip(*b"ecx\x7fho\r\n") {
+        *ended = editor.feed(byte, &mut Discard);
+    }
+    assert_eq!(editor.line(), "echo");
+    // only the `\r` ends the line
+    assert_eq!(ended.iter().filter(|&&ended| ended).count(), 1);
+    assert!(ended[6]);
+}
+
+#[test_case]
+fn test_run_unknown() {
+    assert!(run("  ", &mut Discard).is_ok());
+    assert!(run("frobnicate now", &mut Discard).is_ok());
+}
@@ -1,89 +1,255 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::sync::oneshot;
+use super::{Exit, Priority, Task, TaskId};
+use alloc::{collections::BTreeMap, rc::Rc, sync::Arc, task::Wake, vec::Vec};
 use core::future::Future;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
+use spinning_top::Spinlock;
 
 const MAX_TASKS: usize = 100;
 
+static STATS: Spinlock<SchedulerStats> = Spinlock::new(SchedulerStats::new());
+
+/// Per task accounting, as shown by a `top`-like view
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub priority: Priority,
+    /// How often the task has been polled
+    pub polls: u64,
+    /// Time spent polling the task, in TSC cycles
+    pub cycles: u64,
+}
+
+struct SchedulerStats {
+    tasks: BTreeMap<TaskId, TaskStats>,
+    idle_cycles: u64,
+}
+
+impl SchedulerStats {
+    const fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            idle_cycles: 0,
+        }
+    }
+}
+
+/// Returns a snapshot of the statistics of all live tasks, ordered by [`TaskId`]
+pub fn stats() -> Vec<TaskStats> {
+    STATS.lock().tasks.values().cloned().collect()
+}
+
+/// Returns the time the executor spent halted waiting for interrupts, in TSC cycles
+pub fn idle_cycles() -> u64 {
+    STATS.lock().idle_cycles
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
 #[derive(Clone)]
 #[repr(transparent)]
-pub struct Spawner(Arc<ArrayQueue<Task>>);
+pub struct Spawner(Rc<ArrayQueue<Task>>);
 impl Spawner {
     pub fn new(capacity: usize) -> Self {
-        Self(Arc::new(ArrayQueue::new(capacity)))
+        Self(Rc::new(ArrayQueue::new(capacity)))
+    }
+    /// Adds a task, or returns an error if too many tasks are waiting to be spawned already
+    pub fn add(&self, future: impl Future<Output = ()> + 'static) -> Result<(), SpawnError> {
+        self.push(Task::new(future))
+    }
+    /// Adds a process task, see [`Task::new_process`]
+    pub fn add_process(
+        &self,
+        future: impl Future<Output = ()> + 'static,
+    ) -> Result<(), SpawnError> {
+        self.push(Task::new_process(future))
+    }
+    /// Adds a process task and returns a handle to wait for its end, or an error if too
+    /// many tasks are waiting to be spawned already
+    pub fn spawn_process(
+        &self,
+        future: impl Future<Output = ()> + 'static,
+    ) -> Result<ProcessHandle, SpawnError> {
+        let mut task = Task::new_process(future);
+        let exit = task.watch_exit();
+        let id = task.id();
+        self.push(task)?;
+        Ok(ProcessHandle { id, exit })
+    }
+    pub fn add_with_priority(
+        &self,
+        priority: Priority,
+        future: impl Future<Output = ()> + 'static,
+    ) -> Result<(), SpawnError> {
+        self.push(Task::with_priority(priority, future))
+    }
+    fn push(&self, task: Task) -> Result<(), SpawnError> {
+        self.0.push(task).map_err(|_| SpawnError)
+    }
+}
+
+/// The spawner's queue is full, the task was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnError;
+
+/// A process task started with [`Spawner::spawn_process`]
+pub struct ProcessHandle {
+    id: TaskId,
+    exit: oneshot::Receiver<Exit>,
+}
+
+impl ProcessHandle {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Waits for the task to end
+    pub async fn wait(self) -> Exit {
+        // the executor dropped the task before it ended
+        self.exit.await.unwrap_or(Exit::Killed)
+    }
+}
+
+/// One FIFO of ready tasks per [`Priority`]
+struct RunQueues([ArrayQueue<TaskId>; Priority::ALL.len()]);
+
+impl RunQueues {
+    fn new() -> Self {
+        Self(Priority::ALL.map(|_| ArrayQueue::new(MAX_TASKS)))
     }
-    pub fn add(&self, future: impl Future<Output = ()> + 'static) {
-        let _ = self.0.push(Task::new(future));
+    fn get(&self, priority: Priority) -> &ArrayQueue<TaskId> {
+        &self.0[priority.as_usize()]
+    }
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(ArrayQueue::is_empty)
     }
 }
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    run_queues: Arc<RunQueues>,
     spawner: Spawner,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
 }
 
 impl Executor {
     pub fn new(spawner: Spawner) -> Self {
         Self {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(MAX_TASKS)),
+            run_queues: Arc::new(RunQueues::new()),
             spawner,
             waker_cache: BTreeMap::new(),
         }
     }
     fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        let waker = Arc::new(TaskWaker {
+            task_id,
+            priority: task.priority,
+            queued: AtomicBool::new(false),
+            run_queues: self.run_queues.clone(),
+        });
+        STATS.lock().tasks.insert(
+            task_id,
+            TaskStats {
+                id: task_id,
+                name: task.name,
+                priority: task.priority,
+                polls: 0,
+                cycles: 0,
+            },
+        );
         if self.tasks.insert(task.id, task).is_some() {
             panic!("Task with same ID already in task queue!!");
         }
-        self.task_queue.push(task_id).expect("Task queue full!!");
+        waker.wake_task();
+        self.waker_cache.insert(task_id, waker);
+    }
+    fn spawn_pending(&mut self) {
+        while let Some(task) = self.spawner.0.pop() {
+            self.spawn(task);
+        }
     }
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
         interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.run_queues.is_empty() && self.spawner.0.is_empty() {
+            let start = rdtsc();
             enable_and_hlt();
+            STATS.lock().idle_cycles += rdtsc() - start;
         } else {
             interrupts::enable();
         }
     }
     pub fn run(&mut self) -> ! {
-        while let Some(e) = self.spawner.0.pop() {
-            self.spawn(e);
-        }
         loop {
-            self.run_ready_tasks();
+            self.run_until_idle();
             self.sleep_if_idle();
         }
     }
 
+    /// Starts the tasks added to the spawner and polls them until none is ready, without
+    /// waiting for interrupts
+    pub fn run_until_idle(&mut self) {
+        self.spawn_pending();
+        self.run_ready_tasks();
+    }
+
+    /// Polls ready tasks in weighted rounds until no task is ready.
+    ///
+    /// Each round gives every [`Priority`] up to [`Priority::budget`] polls, and a task that
+    /// wakes itself goes to the back of its queue. So a busy input task can delay, but never
+    /// starve, background work.
     fn run_ready_tasks(&mut self) {
+        while !self.run_queues.is_empty() {
+            for priority in Priority::ALL {
+                for _ in 0..priority.budget() {
+                    match self.run_queues.get(priority).pop() {
+                        Some(task_id) => self.poll_task(task_id),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
         let Self {
-            tasks,
-            task_queue,
-            waker_cache,
-            ..
+            tasks, waker_cache, ..
         } = self;
 
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+        let (task, task_waker) = match (tasks.get_mut(&task_id), waker_cache.get(&task_id)) {
+            (Some(task), Some(waker)) => (task, waker),
+            _ => return, // task no longer exists
+        };
+        task_waker.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task_waker.clone());
+        let mut context = Context::from_waker(&waker);
+
+        let start = rdtsc();
+        let poll = task.poll(&mut context);
+        let elapsed = rdtsc() - start;
+
+        let mut stats = STATS.lock();
+        match poll {
+            Poll::Ready(()) => {
+                // task done -> remove it
+                if let Some(mut task) = tasks.remove(&task_id) {
+                    task.report_exit(Exit::Finished);
+                }
+                waker_cache.remove(&task_id);
+                stats.tasks.remove(&task_id);
+            }
+            Poll::Pending => {
+                if let Some(task_stats) = stats.tasks.get_mut(&task_id) {
+                    task_stats.polls += 1;
+                    task_stats.cycles += elapsed;
                 }
-                Poll::Pending => {}
             }
         }
     }
@@ -91,7 +257,10 @@ impl Executor {
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    /// Set while the task sits in a run queue, so repeated wakeups don't queue it twice
+    queued: AtomicBool,
+    run_queues: Arc<RunQueues>,
 }
 impl Wake for TaskWaker {
     fn wake(self: Arc<Self>) {
@@ -103,13 +272,11 @@ impl Wake for TaskWaker {
 }
 impl TaskWaker {
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
-    }
-    #[allow(clippy::new_ret_no_self)]
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.run_queues
+                .get(self.priority)
+                .push(self.task_id)
+                .expect("task_queue full");
+        }
     }
 }
@@ -0,0 +1,178 @@
+//! Queues that carry items from interrupt handlers to async tasks.
+//!
+//! An [`IrqStream`] is meant to live in a `static` next to the driver, the interrupt handler
+//! [`push`](IrqStream::push)es into it and any number of tasks (up to [`MAX_SUBSCRIBERS`])
+//! read from their own [`Subscriber`].
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{task::AtomicWaker, Stream};
+
+/// Maximum number of subscribers of a single [`IrqStream`]
+pub const MAX_SUBSCRIBERS: usize = 4;
+
+/// What happens to a new item when a subscriber's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Keep the queued items and drop the new one
+    DropNewest,
+    /// Make room by dropping the oldest queued item
+    DropOldest,
+}
+
+struct Slot<T> {
+    active: AtomicBool,
+    queue: OnceCell<ArrayQueue<T>>,
+    waker: AtomicWaker,
+    dropped: AtomicU64,
+}
+
+impl<T> Slot<T> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        active: AtomicBool::new(false),
+        queue: OnceCell::uninit(),
+        waker: AtomicWaker::new(),
+        dropped: AtomicU64::new(0),
+    };
+}
+
+/// A multi-subscriber queue with room for `N` items per subscriber.
+pub struct IrqStream<T, const N: usize> {
+    policy: OverflowPolicy,
+    slots: [Slot<T>; MAX_SUBSCRIBERS],
+    /// Items pushed while nobody was subscribed
+    unheard: AtomicU64,
+}
+
+impl<T: Clone, const N: usize> IrqStream<T, N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            policy,
+            slots: [Slot::EMPTY; MAX_SUBSCRIBERS],
+            unheard: AtomicU64::new(0),
+        }
+    }
+
+    /// Hands an item to every subscriber.
+    ///
+    /// Called from interrupt handlers, so this must not block or allocate.
+    pub fn push(&self, item: T) {
+        let mut heard = false;
+        for slot in self.slots.iter() {
+            if !slot.active.load(Ordering::Acquire) {
+                continue;
+            }
+            let Ok(queue) = slot.queue.try_get() else {
+                continue;
+            };
+            let accepted = match self.policy {
+                OverflowPolicy::DropNewest => queue.push(item.clone()).is_ok(),
+                OverflowPolicy::DropOldest => queue.force_push(item.clone()).is_none(),
+            };
+            if !accepted {
+                slot.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            heard = true;
+            slot.waker.wake();
+        }
+        if !heard {
+            self.unheard.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Starts receiving items pushed from now on, if a subscriber slot is free.
+    ///
+    /// Allocates the queue on first use of a slot, so the heap must be initialized.
+    pub fn try_subscribe(&self) -> Option<Subscriber<'_, T, N>> {
+        let (index, slot) = self.slots.iter().enumerate().find(|(_, slot)| {
+            slot.active
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        let queue = slot.queue.get_or_init(|| ArrayQueue::new(N));
+        // leftovers of a previous subscriber
+        while queue.pop().is_some() {}
+        slot.dropped.store(0, Ordering::Relaxed);
+        Some(Subscriber {
+            stream: self,
+            index,
+        })
+    }
+
+    pub fn subscribe(&self) -> Subscriber<'_, T, N> {
+        self.try_subscribe()
+            .expect("too many subscribers to an IrqStream")
+    }
+
+    /// Total number of items lost, either to full queues or because nobody was listening
+    pub fn dropped(&self) -> u64 {
+        self.slots
+            .iter()
+            .map(|slot| slot.dropped.load(Ordering::Relaxed))
+            .sum::<u64>()
+            + self.unheard.load(Ordering::Relaxed)
+    }
+}
+
+/// A task's handle to an [`IrqStream`], implements [`Stream`]
+pub struct Subscriber<'a, T, const N: usize> {
+    stream: &'a IrqStream<T, N>,
+    index: usize,
+}
+
+impl<T, const N: usize> Subscriber<'_, T, N> {
+    fn slot(&self) -> &Slot<T> {
+        &self.stream.slots[self.index]
+    }
+
+    fn queue(&self) -> &ArrayQueue<T> {
+        self.slot()
+            .queue
+            .try_get()
+            .expect("subscribed slot has a queue")
+    }
+
+    /// Takes the next item without waiting
+    pub fn try_next(&self) -> Option<T> {
+        self.queue().pop()
+    }
+
+    /// Number of items this subscriber lost because its queue was full
+    pub fn dropped(&self) -> u64 {
+        self.slot().dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T, const N: usize> Stream for Subscriber<'_, T, N> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let queue = self.queue();
+
+        // fast
+        if let Some(item) = queue.pop() {
+            return Poll::Ready(Some(item));
+        }
+
+        self.slot().waker.register(cx.waker());
+        match queue.pop() {
+            Some(item) => {
+                self.slot().waker.take();
+                Poll::Ready(Some(item))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Subscriber<'_, T, N> {
+    fn drop(&mut self) {
+        self.slot().waker.take();
+        self.slot().active.store(false, Ordering::Release);
+    }
+}
@@ -1,89 +1,37 @@
+use super::irq_stream::{IrqStream, OverflowPolicy};
+use crate::keyboard::{self, DecodedKey, Decoder, KeyState};
 use crate::print;
-use crate::println;
-use crate::serial_print;
-use conquer_once::spin::OnceCell;
-use core::{
-    pin::Pin,
-    task::{Context, Poll},
-};
-use crossbeam_queue::ArrayQueue;
-use futures_util::{
-    stream::{Stream, StreamExt},
-    task::AtomicWaker,
-};
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use futures_util::stream::StreamExt;
 
-pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore,
-    );
+/// Scancodes read by the keyboard interrupt handler
+pub static SCANCODES: IrqStream<u8, 100> = IrqStream::new(OverflowPolicy::DropNewest);
+
+/// Decodes scancodes into key events for [`keyboard::subscribe`], echoing typed keys to the
+/// console and keeping the LEDs in line with the lock keys
+pub async fn process() {
+    let mut scancodes = SCANCODES.subscribe();
+    let mut decoder = Decoder::new();
+    let mut leds = None;
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
+        let Some(event) = decoder.add(scancode) else {
+            continue;
+        };
+        keyboard::publish(event);
+        if leds != Some(event.modifiers.leds()) {
+            leds = Some(event.modifiers.leds());
+            if let Err(err) = keyboard::set_leds(event.modifiers.leds()) {
+                log::debug!("can't set keyboard LEDs: {:?}", err);
             }
         }
-    }
-}
-
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
-
-static WAKER: AtomicWaker = AtomicWaker::new();
-
-/// Called by the keyboard interrupt handler
-///
-/// Must not block or allocate.
-pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if queue.push(scancode).is_err() {
-            println!("WARNING: scancode queue full; dropping keyboard input");
-        } else {
-            WAKER.wake();
+        if event.state == KeyState::Up {
+            continue;
         }
-    } else {
-        println!("WARNING: scancode queue uninitialized")
-    }
-}
-
-pub struct ScancodeStream {
-    _private: (),
-}
-
-impl Stream for ScancodeStream {
-    type Item = u8;
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let queue = SCANCODE_QUEUE.try_get().expect("not initialized!");
-        if let Some(scancode) = queue.pop() {
-            return Poll::Ready(Some(scancode));
-        }
-        WAKER.register(cx.waker());
-        match queue.pop() {
-            Some(scancode) => {
-                WAKER.take();
-                Poll::Ready(Some(scancode))
-            }
-            None => Poll::Pending,
+        match event.key {
+            // erase the char, the console only moves the cursor back
+            Some(DecodedKey::Unicode('\x08')) => print!("\x08 \x08"),
+            Some(DecodedKey::Unicode(character)) => print!("{}", character),
+            Some(DecodedKey::RawKey(key)) => print!("{:?}", key),
+            None => {}
         }
     }
 }
-
-impl Default for ScancodeStream {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl ScancodeStream {
-    pub fn new() -> Self {
-        SCANCODE_QUEUE
-            .try_init_once(|| ArrayQueue::new(100))
-            .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
-    }
-}
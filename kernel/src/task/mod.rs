@@ -1,7 +1,10 @@
 pub mod executor;
+pub mod irq_stream;
 pub mod keyboard;
 pub mod mouse;
+pub mod serial;
 pub mod simple_executor;
+pub mod sync;
 use alloc::boxed::Box;
 use core::sync::atomic::{AtomicU64, Ordering};
 use core::{
@@ -9,6 +12,7 @@ use core::{
     pin::Pin,
     task::{Context, Poll},
 };
+use sync::oneshot;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
@@ -24,87 +28,106 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// Scheduling class of a [`Task`].
+///
+/// Every class gets a fixed number of polls per scheduling round (see [`Priority::budget`]),
+/// so higher classes are served first but can never starve the lower ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Tasks that drain interrupt-fed queues, like keyboard and mouse input
+    Input,
+    /// Tasks a user is waiting on
+    #[default]
+    Interactive,
+    /// Everything else, like long running WASM programs
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Self::Input, Self::Interactive, Self::Background];
+
+    /// Maximum number of polls this class gets per scheduling round
+    pub const fn budget(self) -> usize {
+        match self {
+            Self::Input => 8,
+            Self::Interactive => 4,
+            Self::Background => 2,
+        }
+    }
+
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// How a process task ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Its future completed
+    Finished,
+    /// It was dropped before it finished
+    Killed,
 }
 
 pub struct Task {
     id: TaskId,
+    name: &'static str,
+    priority: Priority,
+    /// Told how a process task ended, see [`Task::watch_exit`]
+    exit: Option<oneshot::Sender<Exit>>,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        Self::with_priority(Priority::default(), future)
+    }
+    /// Creates a task in the given scheduling class, named after the future's type
+    pub fn with_priority<F: Future<Output = ()> + 'static>(priority: Priority, future: F) -> Self {
+        let name = core::any::type_name::<F>();
         Self {
             id: TaskId::new(),
+            name: name.strip_suffix("::{{closure}}").unwrap_or(name),
+            priority,
+            exit: None,
             future: Box::pin(future),
         }
     }
+    /// Creates a background task running a process, e.g. a WASM program.
+    ///
+    /// WASM programs run in an interpreter, so they end through its traps. A CPU fault while
+    /// polling one is a kernel bug like any other.
+    pub fn new_process<F: Future<Output = ()> + 'static>(future: F) -> Self {
+        Self::with_priority(Priority::Background, future)
+    }
+    /// Returns a receiver for how the task ends. It's kept outside of the future, so it
+    /// still gets told when the task is dropped unfinished.
+    pub fn watch_exit(&mut self) -> oneshot::Receiver<Exit> {
+        let (sender, receiver) = oneshot::channel();
+        self.exit = Some(sender);
+        receiver
+    }
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+    fn report_exit(&mut self, exit: Exit) {
+        if let Some(sender) = self.exit.take() {
+            // the watcher may not care anymore
+            let _ = sender.send(exit);
+        }
+    }
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
 }
-
-/// This macro is used to create a stream that can be polled asynchronously for a given task.
-/// It implements a `write` function that can be used to write to the stream.
-/// To read from the stream, use the generated `TaskStream` struct which implements `Stream`.
-macro_rules! stream_processor_task {
-    ($stream_type:ty, $queue_size:literal) => {
-        use core::{
-            pin::Pin,
-            task::{Context, Poll},
-        };
-
-        use conquer_once::spin::OnceCell;
-        use crossbeam_queue::ArrayQueue;
-        use futures_util::{task::AtomicWaker, Stream, StreamExt};
-        use $crate::println;
-
-        static QUEUE: OnceCell<ArrayQueue<$stream_type>> = OnceCell::uninit();
-        static WAKER: AtomicWaker = AtomicWaker::new();
-
-        pub fn write(scancode: $stream_type) {
-            if let Ok(queue) = QUEUE.try_get() {
-                if queue.push(scancode).is_err() {
-                    println!("queue full, dropping input!");
-                } else {
-                    WAKER.wake();
-                }
-            } else {
-                println!("queue uninitialized, dropping input!");
-            }
-        }
-
-        struct TaskStream {
-            _private: (),
-        }
-        impl TaskStream {
-            fn new() -> Self {
-                QUEUE
-                    .try_init_once(|| ArrayQueue::new($queue_size))
-                    .expect("queue failed to init");
-                Self { _private: () }
-            }
-        }
-        impl Stream for TaskStream {
-            type Item = $stream_type;
-
-            fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<$stream_type>> {
-                let queue = QUEUE.try_get().expect("queue should be initialized by now");
-
-                // fast
-                if let Some(sc) = queue.pop() {
-                    return Poll::Ready(Some(sc));
-                }
-
-                WAKER.register(cx.waker());
-                match queue.pop() {
-                    Some(sc) => {
-                        WAKER.take();
-                        Poll::Ready(Some(sc))
-                    }
-                    None => Poll::Pending,
-                }
-            }
-        }
-    };
-}
-pub(crate) use stream_processor_task;
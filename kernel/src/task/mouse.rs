@@ -1,9 +1,18 @@
-super::stream_processor_task!(u8, 2048);
+use super::irq_stream::{IrqStream, OverflowPolicy};
+use crate::mouse::{self, Decoder, Mouse};
+use futures_util::StreamExt;
+
+/// Bytes of PS/2 mouse packets read by the mouse interrupt handler
+pub static PACKETS: IrqStream<u8, 2048> = IrqStream::new(OverflowPolicy::DropNewest);
 
 pub async fn process() {
-    let mut stream = TaskStream::new();
-    let mouse = crate::mouse::get().expect("mouse should be initialized by now");
-    while let Some(packet) = stream.next().await {
-        mouse.add(packet).await;
+    let mut stream = PACKETS.subscribe();
+    // the packet size depends on what mouse::init found
+    let mut decoder = Decoder::new(mouse::has_wheel());
+    let mut mouse = Mouse::new();
+    while let Some(byte) = stream.next().await {
+        if let Some(packet) = decoder.add(byte) {
+            mouse.report(packet);
+        }
     }
 }
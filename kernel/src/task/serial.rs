@@ -0,0 +1,4 @@
+use super::irq_stream::{IrqStream, OverflowPolicy};
+
+/// Bytes received on COM1, read by the serial interrupt handler
+pub static INPUT: IrqStream<u8, 256> = IrqStream::new(OverflowPolicy::DropNewest);
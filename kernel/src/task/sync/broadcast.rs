@@ -0,0 +1,189 @@
+//! Multi-producer, multi-consumer channels where every receiver sees every message
+use super::WaitQueue;
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{future::poll_fn, task::Poll};
+use spinning_top::Spinlock;
+
+/// There were no receivers to send to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The receiver fell behind and the given number of messages were overwritten
+    Lagged(u64),
+    /// All senders have been dropped and no messages are left
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Lagged(u64),
+    Closed,
+}
+
+struct State<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    /// Sequence number of `buffer[0]`
+    first: u64,
+    senders: usize,
+    receivers: usize,
+}
+
+impl<T> State<T> {
+    fn end(&self) -> u64 {
+        self.first + self.buffer.len() as u64
+    }
+}
+
+struct Shared<T> {
+    state: Spinlock<State<T>>,
+    readers: WaitQueue,
+}
+
+/// Creates a channel retaining the last `capacity` messages for slow receivers
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be non-zero");
+    let shared = Arc::new(Shared {
+        state: Spinlock::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            first: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        readers: WaitQueue::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends a message to all current receivers, returning how many there are.
+    ///
+    /// Never waits; if the buffer is full the oldest message is overwritten.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let receivers = {
+            let mut state = self.shared.state.lock();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+            if state.buffer.len() == state.capacity {
+                state.buffer.pop_front();
+                state.first += 1;
+            }
+            state.buffer.push_back(value);
+            state.receivers
+        };
+        self.shared.readers.wake_all();
+        Ok(receivers)
+    }
+
+    /// Creates a receiver that sees messages sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.end(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock();
+            state.senders -= 1;
+            state.senders == 0
+        };
+        if last {
+            self.shared.readers.wake_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next message to read
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.shared.state.lock();
+        if self.next < state.first {
+            let missed = state.first - self.next;
+            self.next = state.first;
+            return Err(TryRecvError::Lagged(missed));
+        }
+        if self.next == state.end() {
+            return Err(if state.senders == 0 {
+                TryRecvError::Closed
+            } else {
+                TryRecvError::Empty
+            });
+        }
+        let value = state.buffer[(self.next - state.first) as usize].clone();
+        self.next += 1;
+        Ok(value)
+    }
+
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        poll_fn(|cx| {
+            let mut registered = false;
+            loop {
+                match self.try_recv() {
+                    Ok(value) => return Poll::Ready(Ok(value)),
+                    Err(TryRecvError::Lagged(n)) => return Poll::Ready(Err(RecvError::Lagged(n))),
+                    Err(TryRecvError::Closed) => return Poll::Ready(Err(RecvError::Closed)),
+                    Err(TryRecvError::Empty) if registered => return Poll::Pending,
+                    Err(TryRecvError::Empty) => {
+                        self.shared.readers.register(cx.waker());
+                        registered = true;
+                    }
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+    }
+}
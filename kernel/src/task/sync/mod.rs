@@ -0,0 +1,69 @@
+//! Async primitives for communication between kernel tasks.
+//!
+//! These are meant to be used from task context only. Interrupt handlers must not touch them,
+//! as the internal spinlocks are held with interrupts enabled; use an IRQ-fed queue instead.
+pub mod broadcast;
+pub mod lock;
+pub mod mpsc;
+pub mod notify;
+pub mod oneshot;
+
+pub use lock::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use notify::Notify;
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+use spinning_top::Spinlock;
+
+/// A list of wakers of tasks waiting for some condition.
+///
+/// Waiters must register and then re-check their condition, as every wakeup wakes all of them.
+pub(crate) struct WaitQueue(Spinlock<VecDeque<Waker>>);
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self(Spinlock::new(VecDeque::new()))
+    }
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push_back(waker.clone());
+        }
+    }
+    pub fn wake_all(&self) {
+        let wakers = core::mem::take(&mut *self.0.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Waits on multiple futures at once, running the body of the first one that completes.
+/// The remaining futures are dropped.
+///
+/// Branches are polled in order, so earlier branches win if several are ready.
+/// All bodies must evaluate to the same type, which is what the macro evaluates to.
+///
+/// ```ignore
+/// let event = select! {
+///     key = keys.recv() => Event::Key(key),
+///     packet = packets.recv() => Event::Mouse(packet),
+/// };
+/// ```
+#[macro_export]
+macro_rules! select {
+    ($($name:ident = $fut:expr => $body:expr),+ $(,)?) => {{
+        $(let mut $name = core::pin::pin!($fut);)+
+        core::future::poll_fn(|cx| {
+            $(
+                if let core::task::Poll::Ready($name) =
+                    core::future::Future::poll($name.as_mut(), cx)
+                {
+                    return core::task::Poll::Ready($body);
+                }
+            )+
+            core::task::Poll::Pending
+        })
+        .await
+    }};
+}
@@ -0,0 +1,66 @@
+use super::WaitQueue;
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+/// Wakes tasks waiting for an event, without carrying any data.
+///
+/// [`Notify::notify_one`] stores a permit if nobody is waiting, so a notification sent
+/// just before [`Notify::notified`] is awaited is not lost.
+pub struct Notify {
+    permit: AtomicBool,
+    /// Bumped by every [`Notify::notify_waiters`]
+    generation: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            permit: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Wakes one waiting task, or the next one to wait if there is none
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Wakes all tasks that are waiting right now
+    pub fn notify_waiters(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Waits for a notification
+    pub async fn notified(&self) {
+        let generation = self.generation.load(Ordering::Acquire);
+        let ready = || {
+            self.generation.load(Ordering::Acquire) != generation
+                || self.permit.swap(false, Ordering::AcqRel)
+        };
+        poll_fn(|cx| {
+            if ready() {
+                return Poll::Ready(());
+            }
+            self.waiters.register(cx.waker());
+            if ready() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
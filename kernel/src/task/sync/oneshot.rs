@@ -0,0 +1,104 @@
+//! Channels for sending a single value, e.g. a reply to a request
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use spinning_top::Spinlock;
+
+/// The sender was dropped without sending a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct Shared<T> {
+    value: Spinlock<Option<T>>,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+    waker: AtomicWaker,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: Spinlock::new(None),
+        sender_dropped: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends the value, giving it back if the receiver is already gone
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.is_closed() {
+            return Err(value);
+        }
+        *self.shared.value.lock() = Some(value);
+        Ok(())
+        // dropping self wakes the receiver
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.receiver_dropped.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.sender_dropped.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+/// Resolves to the sent value
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    fn try_take(&self) -> Option<Result<T, RecvError>> {
+        // check for the drop first, a value sent before it must not be missed
+        let sender_dropped = self.shared.sender_dropped.load(Ordering::Acquire);
+        match self.shared.value.lock().take() {
+            Some(value) => Some(Ok(value)),
+            None if sender_dropped => Some(Err(RecvError)),
+            None => None,
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(result) = self.try_take() {
+            return Poll::Ready(result);
+        }
+        self.shared.waker.register(cx.waker());
+        match self.try_take() {
+            Some(result) => {
+                self.shared.waker.take();
+                Poll::Ready(result)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_dropped.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,134 @@
+//! Uptime keeping and timers, driven by the PIT timer interrupt
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::task::AtomicWaker;
+use x86_64::instructions::port::Port;
+
+/// Rate of the timer interrupt
+pub const TICK_HZ: u64 = 100;
+/// Input clock of the PIT
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Programs PIT channel 0 to fire [`TICK_HZ`] times a second
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICK_HZ) as u16;
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel0 = Port::<u8>::new(0x40);
+    unsafe {
+        command.write(0x36); // channel 0, lobyte/hibyte, square wave generator
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Called by the timer interrupt handler
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    for timer in TIMERS.iter() {
+        let deadline = timer.deadline.load(Ordering::Acquire);
+        if deadline != FREE && deadline <= now {
+            timer.waker.wake();
+        }
+    }
+}
+
+/// Number of timer interrupts since [`init`]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICK_HZ
+}
+
+/// Maximum number of [`Sleep`]s waiting at the same time, further ones poll on every wakeup
+const MAX_TIMERS: usize = 32;
+/// Deadline of an unused timer slot, no real deadline is 0 as it's always in the future
+const FREE: u64 = 0;
+
+struct Timer {
+    deadline: AtomicU64,
+    waker: AtomicWaker,
+}
+
+impl Timer {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: Self = Self {
+        deadline: AtomicU64::new(FREE),
+        waker: AtomicWaker::new(),
+    };
+}
+
+static TIMERS: [Timer; MAX_TIMERS] = [Timer::FREE; MAX_TIMERS];
+
+/// Waits for at least `ms` milliseconds, rounded up to whole ticks
+pub fn sleep_ms(ms: u64) -> Sleep {
+    sleep_ticks((ms * TICK_HZ).div_ceil(1000))
+}
+
+/// Waits until `ticks` more timer interrupts happened
+pub fn sleep_ticks(ticks: u64) -> Sleep {
+    Sleep {
+        deadline: self::ticks() + ticks.max(1),
+        timer: None,
+    }
+}
+
+/// Future returned by [`sleep_ms`] and [`sleep_ticks`]
+pub struct Sleep {
+    deadline: u64,
+    /// Index into [`TIMERS`], taken on the first poll
+    timer: Option<usize>,
+}
+
+impl Sleep {
+    fn release(&mut self) {
+        if let Some(index) = self.timer.take() {
+            TIMERS[index].waker.take();
+            TIMERS[index].deadline.store(FREE, Ordering::Release);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            self.release();
+            return Poll::Ready(());
+        }
+        if self.timer.is_none() {
+            let deadline = self.deadline;
+            self.timer = TIMERS.iter().position(|timer| {
+                timer
+                    .deadline
+                    .compare_exchange(FREE, deadline, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            });
+        }
+        match self.timer {
+            Some(index) => TIMERS[index].waker.register(cx.waker()),
+            // all timers taken, check again soon
+            None => cx.waker().wake_by_ref(),
+        }
+        // the deadline might have passed before the waker was registered
+        if ticks() >= self.deadline {
+            self.release();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
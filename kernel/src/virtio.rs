@@ -0,0 +1,305 @@
+//! Virtio devices on PCI, see the "Virtual I/O Device (VIRTIO) Version 1.1" specification.
+//!
+//! Only the modern PCI transport and split virtqueues are supported. Devices are polled, as
+//! there is no way to route their interrupts yet.
+pub mod input;
+
+use crate::memory::{self, vma};
+use crate::pci::{Bar, PciDevice};
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+
+const VENDOR_ID: u16 = 0x1af4;
+/// Modern devices are numbered from here, by device type
+const MODERN_DEVICE_BASE: u16 = 0x1040;
+
+/// PCI capability id of the virtio structures
+const CAP_VENDOR: u8 = 0x09;
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_DEVICE: u8 = 4;
+
+// common configuration registers
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0c;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 128;
+
+/// Set by devices that aren't legacy ones, must be accepted
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// Largest queue we set up, so every part of it fits in a page
+const MAX_QUEUE_SIZE: u16 = 256;
+const DESC_F_WRITE: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The device lacks one of the required configuration structures
+    MissingCapability,
+    /// A configuration structure is in an I/O BAR
+    UnsupportedBar,
+    Map(vma::VmaError),
+    /// The device didn't accept our features
+    FeaturesRejected,
+    /// The queue doesn't exist
+    NoQueue(u16),
+    OutOfMemory,
+}
+
+impl From<vma::VmaError> for VirtioError {
+    fn from(err: vma::VmaError) -> Self {
+        Self::Map(err)
+    }
+}
+
+/// Lists the virtio devices of the given type, e.g. 18 for input devices
+pub fn devices(device_type: u16) -> impl Iterator<Item = PciDevice> {
+    crate::pci::devices().filter(move |device| {
+        device.vendor_id == VENDOR_ID && device.device_id == MODERN_DEVICE_BASE + device_type
+    })
+}
+
+unsafe fn read<T: Copy>(addr: VirtAddr) -> T {
+    ptr::read_volatile(addr.as_ptr())
+}
+
+unsafe fn write<T>(addr: VirtAddr, value: T) {
+    ptr::write_volatile(addr.as_mut_ptr(), value)
+}
+
+/// The configuration structures of a device, mapped from its BARs
+pub struct Transport {
+    common: VirtAddr,
+    notify: VirtAddr,
+    notify_multiplier: u32,
+    device_config: VirtAddr,
+}
+
+impl Transport {
+    /// Finds and maps the configuration structures, and enables the device's bus mastering
+    pub fn new(device: &PciDevice) -> Result<Self, VirtioError> {
+        let (mut common, mut notify, mut device_config) = (None, None, None);
+        let mut notify_multiplier = 0;
+        let address = device.address;
+        for cap in device.capabilities().filter(|cap| cap.id == CAP_VENDOR) {
+            let cfg_type = address.read_u8(cap.offset + 3);
+            let slot = match cfg_type {
+                CFG_COMMON => &mut common,
+                CFG_NOTIFY => &mut notify,
+                CFG_DEVICE => &mut device_config,
+                _ => continue,
+            };
+            // the first one of each type is the preferred one
+            if slot.is_some() {
+                continue;
+            }
+            let Some(Bar::Memory { address: bar, .. }) =
+                device.bar(address.read_u8(cap.offset + 4))
+            else {
+                return Err(VirtioError::UnsupportedBar);
+            };
+            let offset = u64::from(address.read(cap.offset + 8));
+            let length = u64::from(address.read(cap.offset + 12));
+            *slot = Some(vma::map_device(
+                "virtio",
+                PhysAddr::new(bar + offset),
+                length,
+            )?);
+            if cfg_type == CFG_NOTIFY {
+                notify_multiplier = address.read(cap.offset + 16);
+            }
+        }
+        device.enable_bus_master();
+        match (common, notify, device_config) {
+            (Some(common), Some(notify), Some(device_config)) => Ok(Self {
+                common,
+                notify,
+                notify_multiplier,
+                device_config,
+            }),
+            _ => Err(VirtioError::MissingCapability),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { read(self.common + COMMON_DEVICE_STATUS) }
+    }
+
+    fn set_status(&mut self, status: u8) {
+        unsafe { write(self.common + COMMON_DEVICE_STATUS, status) }
+    }
+
+    /// Resets the device and negotiates features, `wanted` are the device specific ones
+    /// the driver understands. Returns the accepted ones.
+    pub fn init(&mut self, wanted: u64) -> Result<u64, VirtioError> {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut offered = 0;
+        for half in 0..2u32 {
+            unsafe {
+                write(self.common + COMMON_DEVICE_FEATURE_SELECT, half);
+                offered |=
+                    u64::from(read::<u32>(self.common + COMMON_DEVICE_FEATURE)) << (32 * half);
+            }
+        }
+        let features = offered & (wanted | FEATURE_VERSION_1);
+        for half in 0..2u32 {
+            unsafe {
+                write(self.common + COMMON_DRIVER_FEATURE_SELECT, half);
+                write(
+                    self.common + COMMON_DRIVER_FEATURE,
+                    (features >> (32 * half)) as u32,
+                );
+            }
+        }
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        self.set_status(status);
+        if features & FEATURE_VERSION_1 == 0 || self.status() & STATUS_FEATURES_OK == 0 {
+            self.set_status(status | STATUS_FAILED);
+            return Err(VirtioError::FeaturesRejected);
+        }
+        Ok(features)
+    }
+
+    /// Allocates and enables queue `index`, with up to `max_size` entries
+    pub fn setup_queue(&mut self, index: u16, max_size: u16) -> Result<Virtqueue, VirtioError> {
+        let common = self.common;
+        unsafe { write(common + COMMON_QUEUE_SELECT, index) };
+        let size = unsafe { read::<u16>(common + COMMON_QUEUE_SIZE) };
+        if size == 0 {
+            return Err(VirtioError::NoQueue(index));
+        }
+        // sizes are powers of two, and so are the maxima
+        let size = size.min(max_size).min(MAX_QUEUE_SIZE);
+        let mut parts = [(PhysAddr::zero(), VirtAddr::zero()); 3];
+        for part in parts.iter_mut() {
+            *part = memory::allocate_dma_frame().ok_or(VirtioError::OutOfMemory)?;
+        }
+        let [desc, avail, used] = parts;
+        let notify_off = unsafe {
+            write(common + COMMON_QUEUE_SIZE, size);
+            for (register, (phys, _)) in [
+                (COMMON_QUEUE_DESC, desc),
+                (COMMON_QUEUE_DRIVER, avail),
+                (COMMON_QUEUE_DEVICE, used),
+            ] {
+                // 64 bit registers may be written as two halves
+                write(common + register, phys.as_u64() as u32);
+                write(common + register + 4u64, (phys.as_u64() >> 32) as u32);
+            }
+            let notify_off = read::<u16>(common + COMMON_QUEUE_NOTIFY_OFF);
+            write(common + COMMON_QUEUE_ENABLE, 1u16);
+            notify_off
+        };
+        Ok(Virtqueue {
+            index,
+            size,
+            desc: desc.1,
+            avail: avail.1,
+            used: used.1,
+            notify: self.notify + u64::from(notify_off) * u64::from(self.notify_multiplier),
+            next_avail: 0,
+            last_used: 0,
+        })
+    }
+
+    /// Tells the device the driver is ready, after setting up the queues
+    pub fn driver_ok(&mut self) {
+        let status = self.status();
+        self.set_status(status | STATUS_DRIVER_OK);
+    }
+
+    /// Reads a byte of the device specific configuration
+    pub fn config_read(&self, offset: usize) -> u8 {
+        unsafe { read(self.device_config + offset) }
+    }
+
+    pub fn config_write(&mut self, offset: usize, value: u8) {
+        unsafe { write(self.device_config + offset, value) }
+    }
+}
+
+/// A split virtqueue, whose descriptors are handed to the device and come back used
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    /// The descriptor table, available ring and used ring, each in its own frame
+    desc: VirtAddr,
+    avail: VirtAddr,
+    used: VirtAddr,
+    notify: VirtAddr,
+    /// Our index into the available ring
+    next_avail: u16,
+    /// Index of the next used ring entry to look at
+    last_used: u16,
+}
+
+impl Virtqueue {
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Points descriptor `id` at a buffer. `device_writable` ones are filled by the device.
+    pub fn set_descriptor(&mut self, id: u16, buffer: PhysAddr, len: u32, device_writable: bool) {
+        assert!(id < self.size, "descriptor out of range");
+        let desc = self.desc + u64::from(id) * 16;
+        let flags = if device_writable { DESC_F_WRITE } else { 0 };
+        unsafe {
+            write(desc, buffer.as_u64());
+            write(desc + 8u64, len);
+            write(desc + 12u64, flags);
+            write(desc + 14u64, 0u16);
+        }
+    }
+
+    /// Hands descriptor `id` to the device, which only notices after [`Virtqueue::notify`]
+    pub fn make_available(&mut self, id: u16) {
+        let slot = self.next_avail % self.size;
+        self.next_avail = self.next_avail.wrapping_add(1);
+        unsafe {
+            write(self.avail + 4u64 + u64::from(slot) * 2, id);
+            // the entry must be visible before the index that covers it
+            fence(Ordering::Release);
+            write(self.avail + 2u64, self.next_avail);
+        }
+    }
+
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        unsafe { write(self.notify, self.index) }
+    }
+
+    /// Takes the next descriptor the device is done with, and how many bytes it wrote
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { read::<u16>(self.used + 2u64) };
+        if used_idx == self.last_used {
+            return None;
+        }
+        // the entry is only valid once the index was read
+        fence(Ordering::Acquire);
+        let entry = self.used + 4u64 + u64::from(self.last_used % self.size) * 8;
+        self.last_used = self.last_used.wrapping_add(1);
+        let (id, len) = unsafe { (read::<u32>(entry), read::<u32>(entry + 4u64)) };
+        Some((id as u16, len))
+    }
+}
@@ -0,0 +1,297 @@
+//! Driver for virtio input devices, like QEMU's `virtio-keyboard`, `virtio-mouse` and
+//! `virtio-tablet`.
+//!
+//! Their events use the Linux evdev codes. Keys are translated to set 1 scancodes and pushed
+//! into [`SCANCODES`], like the PS/2 keyboard's, and pointer events are reported through a
+//! [`Mouse`], so the rest of the kernel doesn't see a difference.
+use super::{Transport, VirtioError, Virtqueue};
+use crate::memory;
+use crate::mouse::{Mouse, MouseButton, Packet};
+use crate::task::keyboard::SCANCODES;
+use alloc::{string::String, vec::Vec};
+use x86_64::{PhysAddr, VirtAddr};
+
+const DEVICE_TYPE: u16 = 18;
+const EVENT_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+/// Size of a `virtio_input_event`
+const EVENT_SIZE: u64 = 8;
+/// How often the event queue is checked
+const POLL_TICKS: u64 = 1;
+
+// device configuration: the device answers a query by `select` and `subsel`
+const CONFIG_SELECT: usize = 0;
+const CONFIG_SUBSEL: usize = 1;
+const CONFIG_SIZE: usize = 2;
+const CONFIG_DATA: usize = 8;
+const CFG_ID_NAME: u8 = 0x01;
+const CFG_EV_BITS: u8 = 0x11;
+const CFG_ABS_INFO: u8 = 0x12;
+
+// event types and codes, from Linux' input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+/// Range of an absolute axis
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    min: i32,
+    max: i32,
+}
+
+pub struct InputDevice {
+    name: String,
+    queue: Virtqueue,
+    /// One event buffer per descriptor
+    buffers: (PhysAddr, VirtAddr),
+    mouse: Mouse,
+    /// Pointer state collected until the next `SYN_REPORT`
+    packet: Packet,
+    absolute: (Option<i32>, Option<i32>),
+    axes: [Axis; 2],
+}
+
+/// Sets up every virtio input device, to be run with [`InputDevice::run`]
+pub fn probe() -> Vec<InputDevice> {
+    super::devices(DEVICE_TYPE)
+        .filter_map(|pci| match InputDevice::new(&pci) {
+            Ok(device) => {
+                log::info!("virtio input {}: {}", pci.address, device.name());
+                Some(device)
+            }
+            Err(err) => {
+                log::warn!("virtio input {}: {:?}", pci.address, err);
+                None
+            }
+        })
+        .collect()
+}
+
+impl InputDevice {
+    fn new(pci: &crate::pci::PciDevice) -> Result<Self, VirtioError> {
+        let mut transport = Transport::new(pci)?;
+        transport.init(0)?;
+        let mut queue = transport.setup_queue(EVENT_QUEUE, QUEUE_SIZE)?;
+        let buffers = memory::allocate_dma_frame().ok_or(VirtioError::OutOfMemory)?;
+        for id in 0..queue.size() {
+            let buffer = buffers.0 + u64::from(id) * EVENT_SIZE;
+            queue.set_descriptor(id, buffer, EVENT_SIZE as u32, true);
+            queue.make_available(id);
+        }
+        transport.driver_ok();
+        queue.notify();
+
+        let name = String::from_utf8_lossy(&query(&mut transport, CFG_ID_NAME, 0)).into();
+        let axes = [ABS_X, ABS_Y].map(|code| {
+            let info = query(&mut transport, CFG_ABS_INFO, code as u8);
+            let field = |i: usize| {
+                info.get(i..i + 4)
+                    .map_or(0, |bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+            };
+            Axis {
+                min: field(0),
+                max: field(4),
+            }
+        });
+        let pointing = [EV_REL, EV_ABS]
+            .into_iter()
+            .any(|kind| !query(&mut transport, CFG_EV_BITS, kind as u8).is_empty());
+        if pointing {
+            crate::mouse::show_pointer();
+        }
+        Ok(Self {
+            name,
+            queue,
+            buffers,
+            mouse: Mouse::new(),
+            packet: Packet::default(),
+            absolute: (None, None),
+            axes,
+        })
+    }
+
+    /// The name the device reports, like "QEMU Virtio Keyboard"
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Handles events as they come, meant to be spawned as a task
+    pub async fn run(mut self) {
+        loop {
+            self.poll();
+            crate::time::sleep_ticks(POLL_TICKS).await;
+        }
+    }
+
+    fn poll(&mut self) {
+        let mut returned = false;
+        while let Some((id, _len)) = self.queue.pop_used() {
+            let buffer = self.buffers.1 + u64::from(id) * EVENT_SIZE;
+            let event = unsafe {
+                let ptr = buffer.as_ptr::<u8>();
+                let bytes = core::ptr::read_volatile(ptr as *const [u8; EVENT_SIZE as usize]);
+                Event {
+                    kind: u16::from_le_bytes([bytes[0], bytes[1]]),
+                    code: u16::from_le_bytes([bytes[2], bytes[3]]),
+                    value: i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+                }
+            };
+            self.handle(event);
+            self.queue.make_available(id);
+            returned = true;
+        }
+        if returned {
+            self.queue.notify();
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        let Event { kind, code, value } = event;
+        match (kind, code) {
+            (EV_KEY, BTN_LEFT) => self.packet.buttons.set(MouseButton::Left, value != 0),
+            (EV_KEY, BTN_RIGHT) => self.packet.buttons.set(MouseButton::Right, value != 0),
+            (EV_KEY, BTN_MIDDLE) => self.packet.buttons.set(MouseButton::Middle, value != 0),
+            (EV_KEY, _) => push_scancode(code, value),
+            // evdev counts y downwards, PS/2 packets upwards
+            (EV_REL, REL_X) => self.packet.dx = self.packet.dx.saturating_add(clamp(value)),
+            (EV_REL, REL_Y) => self.packet.dy = self.packet.dy.saturating_sub(clamp(value)),
+            // positive is away from the user
+            (EV_REL, REL_WHEEL) => {
+                let steps = value.clamp(i8::MIN.into(), i8::MAX.into()) as i8;
+                self.packet.wheel = self.packet.wheel.saturating_sub(steps);
+            }
+            (EV_ABS, ABS_X) => self.absolute.0 = Some(value),
+            (EV_ABS, ABS_Y) => self.absolute.1 = Some(value),
+            (EV_SYN, SYN_REPORT) => self.report(),
+            _ => {}
+        }
+    }
+
+    /// Hands the pointer state since the last report to the mouse
+    fn report(&mut self) {
+        if self.absolute != (None, None) {
+            let (width, height) = crate::framebuffer::FRAMEBUFFER.get().map_or((1, 1), |fb| {
+                x86_64::instructions::interrupts::without_interrupts(|| fb.lock().size())
+            });
+            let (x, y) = crate::mouse::position();
+            let x = self
+                .absolute
+                .0
+                .map_or(x, |value| scale(value, self.axes[0], width));
+            let y = self
+                .absolute
+                .1
+                .map_or(y, |value| scale(value, self.axes[1], height));
+            self.mouse.move_to(x, y);
+            self.absolute = (None, None);
+        }
+        self.mouse.report(self.packet);
+        self.packet = Packet {
+            buttons: self.packet.buttons,
+            ..Packet::default()
+        };
+    }
+}
+
+/// Asks the device for a piece of configuration, empty if it has none
+fn query(transport: &mut Transport, select: u8, subsel: u8) -> Vec<u8> {
+    transport.config_write(CONFIG_SELECT, select);
+    transport.config_write(CONFIG_SUBSEL, subsel);
+    let size = usize::from(transport.config_read(CONFIG_SIZE));
+    (0..size)
+        .map(|i| transport.config_read(CONFIG_DATA + i))
+        .collect()
+}
+
+fn clamp(value: i32) -> i16 {
+    value.clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+/// Maps a value of an absolute axis onto `0..size`
+fn scale(value: i32, axis: Axis, size: usize) -> usize {
+    let range = i64::from(axis.max) - i64::from(axis.min);
+    if range <= 0 {
+        return 0;
+    }
+    let offset = (i64::from(value) - i64::from(axis.min)).clamp(0, range);
+    (offset * (size as i64 - 1) / range) as usize
+}
+
+/// Feeds a key press (1), repeat (2) or release (0) to the keyboard as set 1 scancodes
+fn push_scancode(code: u16, value: i32) {
+    let Some((extended, scancode)) = scancode(code) else {
+        return;
+    };
+    let release = if value == 0 { 0x80 } else { 0 };
+    if extended {
+        SCANCODES.push(0xe0);
+    }
+    SCANCODES.push(scancode | release);
+}
+
+/// The set 1 scancode of an evdev key code, and whether it has the `0xe0` prefix
+fn scancode(code: u16) -> Option<(bool, u8)> {
+    // the codes of the original keys are their set 1 scancodes
+    if let 1..=88 = code {
+        return Some((false, code as u8));
+    }
+    let scancode = match code {
+        96 => 0x1c,  // keypad enter
+        97 => 0x1d,  // right ctrl
+        98 => 0x35,  // keypad slash
+        99 => 0x37,  // print screen
+        100 => 0x38, // right alt
+        102 => 0x47, // home
+        103 => 0x48, // up
+        104 => 0x49, // page up
+        105 => 0x4b, // left
+        106 => 0x4d, // right
+        107 => 0x4f, // end
+        108 => 0x50, // down
+        109 => 0x51, // page down
+        110 => 0x52, // insert
+        111 => 0x53, // delete
+        125 => 0x5b, // left meta
+        126 => 0x5c, // right meta
+        127 => 0x5d, // menu
+        _ => return None,
+    };
+    Some((true, scancode))
+}
+
+#[test_case]
+fn test_scancode() {
+    assert_eq!(scancode(1), Some((false, 0x01))); // escape
+    assert_eq!(scancode(30), Some((false, 0x1e))); // a
+    assert_eq!(scancode(103), Some((true, 0x48))); // up
+    assert_eq!(scancode(BTN_LEFT), None);
+}
+
+#[test_case]
+fn test_scale() {
+    let axis = Axis {
+        min: 0,
+        max: 0x7fff,
+    };
+    assert_eq!(scale(0, axis, 1024), 0);
+    assert_eq!(scale(0x7fff, axis, 1024), 1023);
+    assert_eq!(scale(-5, axis, 1024), 0);
+}
@@ -1,29 +1,115 @@
-use kernel::println;
-//use wasmi::{Engine, Module};
+//! Runs WASM programs, with host functions in the `host` module. A program only gets the ones
+//! its [capabilities](capability) allow:
+//!
+//! - `hello(i32)` prints the number
+//! - `arg_count() -> i32` returns the number of arguments
+//! - `arg(index: i32, ptr: i32, len: i32) -> i32` copies as much of an argument to `ptr` as
+//!   fits in `len` bytes and returns its full length, or -1 if there is no such argument or
+//!   `ptr` is out of bounds
+//! - `env(name_ptr: i32, name_len: i32, ptr: i32, len: i32) -> i32` does the same for the
+//!   environment variable whose name is at `name_ptr`
+//! - `mouse_event(ptr: i32) -> i32` writes the next mouse event to `ptr` and returns 1, or
+//!   returns 0 if there is none and -1 if `ptr` is out of bounds. An event is 32 bytes, all
+//!   little endian: kind (u32: 1 move, 2 button down, 3 button up, 4 wheel), then two i32
+//!   values (dx and dy; the button, 0 left, 1 right, 2 middle; wheel steps), the position as
+//!   two u32, the held buttons as u32 bitmask and the uptime in ms as u64.
+//! - `key_event(ptr: i32) -> i32` works the same for key events, which are 24 bytes: the key
+//!   (u32, its [number](crate::keyboard::KEYS)), the state (u32: 0 up, 1 down, 2 repeated, 3
+//!   pressed without release), the modifiers as u32 bitmask in the order of
+//!   `KeyModifier::ALL`, the typed character as u32 or 0 if none, and the uptime in ms as u64.
+//! - `channel_create`, `channel_send`, `channel_recv`, `wait`, `handle_close`, `port_bind` and
+//!   `port_connect` pass messages to other programs, and `shm_create`, `shm_size`, `shm_read`,
+//!   `shm_write` and `handle_duplicate` share memory with them, see the `ipc` submodule
+//! - `file_read(path_ptr: i32, path_len: i32, offset: i32, ptr: i32, len: i32) -> i32` copies
+//!   up to `len` bytes at `offset` of a file on the ramdisk to `ptr` and returns how many
+//! - `spawn(path_ptr: i32, path_len: i32, caps_ptr: i32, caps_len: i32) -> i32` starts the
+//!   program at the path with the capabilities listed at `caps_ptr`, which must be a subset of
+//!   its own, and returns its task id
+//!
+//! The last two return a negative [`IpcError::code`] on failure.
+//!
+//! Strings are UTF-8 and not NUL terminated.
+use crate::ipc::{HandleTable, IpcError};
+use crate::keyboard::{self, DecodedKey, KeyEvent, KeyState};
+use crate::mouse::{MouseButton, MouseEvent, MouseEventKind};
+use crate::println;
+use crate::task::executor::Spawner;
+use crate::task::sync::broadcast::{Receiver, TryRecvError};
+use crate::task::TaskId;
+use alloc::{format, string::String, vec, vec::Vec};
 use wasmi::*;
 
+pub mod capability;
+mod ipc;
+
+pub use capability::{Capabilities, Capability, CapabilityError};
+
+/// The program run when nothing else is configured
+pub const EXAMPLE: &[u8] = include_bytes!("../../test.wasm");
+/// Longest environment variable name a program may ask for
+const MAX_NAME_LEN: u32 = 256;
+/// Longest path for `file_read` and `spawn`
+const MAX_PATH_LEN: u32 = 256;
+/// Longest capability list for `spawn`
+const MAX_CAPS_LEN: u32 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WasmError {
+    /// Not a valid WASM module
+    Invalid(String),
+    /// Linking or running the start function failed, e.g. an import is missing
+    Instantiate(String),
+    /// Neither `_start` nor `hello` is exported
+    NoEntry,
+    /// The program trapped
+    Trap(String),
+    /// The program imports a host function its capabilities don't allow
+    Denied(String),
+}
+
+/// Data of a running program, available to host functions
+struct HostState {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    handles: HandleTable,
+    caps: Capabilities,
+    /// For starting child processes
+    spawner: Spawner,
+    /// Mouse events the program didn't read yet, if it may
+    mouse: Option<Receiver<MouseEvent>>,
+    keys: Option<Receiver<KeyEvent>>,
+}
+
 pub fn read_wasm_string(offset: u32, length: u32, wasm_mem: &[u8]) -> &str {
     ::core::str::from_utf8(&wasm_mem[offset as usize..offset as usize + length as usize])
         .expect("read_wasm_cstring failed to parse invalid utf-8 string")
 }
 
-pub async fn example_exec() {
+/// Runs a program to its end, starting at its `_start` export, or `hello` for the example
+pub async fn run(
+    wasm: &'static [u8],
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    caps: Capabilities,
+    spawner: Spawner,
+) -> Result<(), WasmError> {
     // First step is to create the Wasm execution engine with some config.
     // In this example we are using the default configuration.
     let engine = Engine::default();
-    let wasm = include_bytes!("../../test.wasm");
-    let module = Module::new(&engine, &wasm[..]).unwrap();
+    let module = Module::new(&engine, wasm).map_err(|err| WasmError::Invalid(format!("{err}")))?;
 
     // All Wasm objects operate within the context of a `Store`.
-    // Each `Store` has a type parameter to store host-specific data,
-    // which in this case we are using `42` for.
-    type HostState = u32;
-
-    let mut store = Store::new(&engine, 42);
-    let host_hello = Func::wrap(&mut store, |caller: Caller<'_, HostState>, param: i32| {
-        println!("Got {} from WebAssembly", param);
-        println!("My host state is: {}", caller.data());
-    });
+    // Each `Store` has a type parameter to store host-specific data.
+    let state = HostState {
+        args,
+        env,
+        handles: HandleTable::new(),
+        mouse: caps.has(Capability::Input).then(crate::mouse::subscribe),
+        keys: caps.has(Capability::Input).then(crate::keyboard::subscribe),
+        caps,
+        spawner,
+    };
+    let mut store = Store::new(&engine, state);
 
     // In order to create Wasm module instances and link their imports
     // and exports we require a `Linker`.
@@ -33,14 +119,296 @@ pub async fn example_exec() {
     // type signature of the function with `get_typed_func`.
     //
     // Also before using an instance created this way we need to start it.
-    linker.define("host", "hello", host_hello).unwrap();
+    let caps = store.data().caps.clone();
+    for (name, needs, func) in host_functions(&mut store) {
+        if needs.iter().all(|&needs| caps.has(needs)) {
+            linker.define("host", name, func).unwrap();
+        } else if module
+            .imports()
+            .any(|import| import.module() == "host" && import.name() == name)
+        {
+            return Err(WasmError::Denied(format!("host.{name}")));
+        }
+    }
     let instance = linker
         .instantiate(&mut store, &module)
-        .unwrap()
-        .start(&mut store)
-        .unwrap();
-    let hello = instance.get_typed_func::<(), ()>(&store, "hello").unwrap();
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|err| WasmError::Instantiate(format!("{err}")))?;
+    let entry = ["_start", "hello"]
+        .into_iter()
+        .find_map(|name| instance.get_typed_func::<(), ()>(&store, name).ok())
+        .ok_or(WasmError::NoEntry)?;
+
+    // And finally we can call the wasm! It's suspended whenever it waits for a message.
+    let mut call = entry
+        .call_resumable(&mut store, ())
+        .map_err(|err| WasmError::Trap(format!("{err}")))?;
+    loop {
+        let invocation = match call {
+            TypedResumableCall::Finished(()) => return Ok(()),
+            TypedResumableCall::Resumable(invocation) => invocation,
+        };
+        let Some(wait) = invocation.host_error().downcast_ref::<ipc::Wait>() else {
+            return Err(WasmError::Trap(format!("{}", invocation.host_error())));
+        };
+        let result = wait.wait(&mut store.data_mut().handles).await;
+        call = invocation
+            .resume(&mut store, &[Value::I32(result)])
+            .map_err(|err| WasmError::Trap(format!("{err}")))?;
+    }
+}
+
+/// All host functions, with the capability they need
+fn host_functions(store: &mut Store<HostState>) -> Vec<(&'static str, Option<Capability>, Func)> {
+    let mut functions = vec![
+        (
+            "hello",
+            Some(Capability::Console),
+            Func::wrap(&mut *store, host_hello),
+        ),
+        ("arg_count", None, Func::wrap(&mut *store, host_arg_count)),
+        ("arg", None, Func::wrap(&mut *store, host_arg)),
+        ("env", None, Func::wrap(&mut *store, host_env)),
+        (
+            "mouse_event",
+            Some(Capability::Input),
+            Func::wrap(&mut *store, host_mouse_event),
+        ),
+        (
+            "key_event",
+            Some(Capability::Input),
+            Func::wrap(&mut *store, host_key_event),
+        ),
+        (
+            "file_read",
+            Some(Capability::Dir),
+            Func::wrap(&mut *store, host_file_read),
+        ),
+        (
+            "spawn",
+            Some(Capability::Spawn),
+            Func::wrap(&mut *store, host_spawn),
+        ),
+    ];
+    functions.extend(ipc::functions(store));
+    functions
+}
+
+fn host_hello(_caller: Caller<'_, HostState>, param: i32) {
+    println!("Got {} from WebAssembly", param);
+}
+
+fn host_arg_count(caller: Caller<'_, HostState>) -> i32 {
+    caller.data().args.len() as i32
+}
+
+fn host_arg(caller: Caller<'_, HostState>, index: u32, ptr: u32, len: u32) -> i32 {
+    match caller.data().args.get(index as usize).cloned() {
+        Some(arg) => write_str(caller, ptr, len, &arg),
+        None => -1,
+    }
+}
+
+fn host_env(
+    caller: Caller<'_, HostState>,
+    name_ptr: u32,
+    name_len: u32,
+    ptr: u32,
+    len: u32,
+) -> i32 {
+    if name_len > MAX_NAME_LEN {
+        return -1;
+    }
+    let Some(name) = read_memory(&caller, name_ptr, name_len) else {
+        return -1;
+    };
+    let value = caller
+        .data()
+        .env
+        .iter()
+        .find(|(key, _)| key.as_bytes() == name)
+        .map(|(_, value)| value.clone());
+    match value {
+        Some(value) => write_str(caller, ptr, len, &value),
+        None => -1,
+    }
+}
+
+/// Copies `len` bytes out of the program's memory, callers limit `len`
+fn read_memory(caller: &Caller<'_, HostState>, ptr: u32, len: u32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let mut bytes = vec![0; len as usize];
+    memory.read(caller, ptr as usize, &mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Reads a UTF-8 string of at most `max_len` bytes out of the program's memory
+fn read_string(
+    caller: &Caller<'_, HostState>,
+    ptr: u32,
+    len: u32,
+    max_len: u32,
+) -> Result<String, IpcError> {
+    if len > max_len {
+        return Err(IpcError::Invalid);
+    }
+    let bytes = read_memory(caller, ptr, len).ok_or(IpcError::Invalid)?;
+    String::from_utf8(bytes).map_err(|_| IpcError::Invalid)
+}
+
+/// Copies as much of `value` as fits in `len` bytes to `ptr`, returning its full length or
+/// -1 if `ptr` is out of bounds
+fn write_str(caller: Caller<'_, HostState>, ptr: u32, len: u32, value: &str) -> i32 {
+    let bytes = &value.as_bytes()[..value.len().min(len as usize)];
+    match write_event(caller, ptr, bytes) {
+        1 => value.len() as i32,
+        error => error,
+    }
+}
+
+fn host_file_read(
+    caller: Caller<'_, HostState>,
+    path_ptr: u32,
+    path_len: u32,
+    offset: u32,
+    ptr: u32,
+    len: u32,
+) -> i32 {
+    let file = read_string(&caller, path_ptr, path_len, MAX_PATH_LEN).and_then(|path| {
+        if !caller.data().caps.can_read(&path) {
+            return Err(IpcError::AccessDenied);
+        }
+        crate::ramdisk::get(&path).ok_or(IpcError::NotFound)
+    });
+    let file = match file {
+        Ok(file) => file,
+        Err(err) => return err.code(),
+    };
+    let start = file.len().min(offset as usize);
+    let bytes = &file[start..file.len().min(start + len as usize)];
+    match write_event(caller, ptr, bytes) {
+        1 => bytes.len() as i32,
+        _ => IpcError::Invalid.code(),
+    }
+}
+
+fn host_spawn(
+    caller: Caller<'_, HostState>,
+    path_ptr: u32,
+    path_len: u32,
+    caps_ptr: u32,
+    caps_len: u32,
+) -> i32 {
+    match spawn(&caller, path_ptr, path_len, caps_ptr, caps_len) {
+        Ok(id) => id.as_u64() as i32,
+        Err(err) => err.code(),
+    }
+}
+
+/// Starts a child process, with no more capabilities than its parent
+fn spawn(
+    caller: &Caller<'_, HostState>,
+    path_ptr: u32,
+    path_len: u32,
+    caps_ptr: u32,
+    caps_len: u32,
+) -> Result<TaskId, IpcError> {
+    let path = read_string(caller, path_ptr, path_len, MAX_PATH_LEN)?;
+    let spec = read_string(caller, caps_ptr, caps_len, MAX_CAPS_LEN)?;
+    let caps = Capabilities::parse(&spec).map_err(|_| IpcError::Invalid)?;
+    let parent = caller.data();
+    if !parent.caps.can_read(&path) || !caps.is_subset_of(&parent.caps) {
+        return Err(IpcError::AccessDenied);
+    }
+    let program = crate::ramdisk::get(&path).ok_or(IpcError::NotFound)?;
+    let spawner = parent.spawner.clone();
+    let process = parent
+        .spawner
+        .spawn_process(async move {
+            let args = vec![path.clone()];
+            if let Err(err) = run(program, args, Vec::new(), caps, spawner).await {
+                log::warn!("{path}: {err:?}");
+            }
+        })
+        .map_err(|_| IpcError::Full)?;
+    Ok(process.id())
+}
+
+fn host_mouse_event(mut caller: Caller<'_, HostState>, ptr: u32) -> i32 {
+    match caller.data_mut().mouse.as_mut().and_then(next_event) {
+        Some(event) => write_event(caller, ptr, &encode_mouse_event(&event)),
+        None => 0,
+    }
+}
+
+fn host_key_event(mut caller: Caller<'_, HostState>, ptr: u32) -> i32 {
+    match caller.data_mut().keys.as_mut().and_then(next_event) {
+        Some(event) => write_event(caller, ptr, &encode_key_event(&event)),
+        None => 0,
+    }
+}
+
+fn next_event<T: Clone>(receiver: &mut Receiver<T>) -> Option<T> {
+    loop {
+        match receiver.try_recv() {
+            Ok(event) => return Some(event),
+            // the oldest events are gone, the next one is still fine
+            Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Copies bytes into the program's memory, returning 1 or -1 if they don't fit
+fn write_event(mut caller: Caller<'_, HostState>, ptr: u32, bytes: &[u8]) -> i32 {
+    let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+        return -1;
+    };
+    match memory.write(&mut caller, ptr as usize, bytes) {
+        Ok(()) => 1,
+        Err(_) => -1,
+    }
+}
+
+fn encode_mouse_event(event: &MouseEvent) -> [u8; 32] {
+    let button = |button: MouseButton| match button {
+        MouseButton::Left => 0,
+        MouseButton::Right => 1,
+        MouseButton::Middle => 2,
+    };
+    let (kind, a, b): (u32, i32, i32) = match event.kind {
+        MouseEventKind::Move { dx, dy } => (1, dx.into(), dy.into()),
+        MouseEventKind::ButtonDown(pressed) => (2, button(pressed), 0),
+        MouseEventKind::ButtonUp(released) => (3, button(released), 0),
+        MouseEventKind::Wheel(steps) => (4, steps.into(), 0),
+    };
+    let mut bytes = [0; 32];
+    bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+    bytes[4..8].copy_from_slice(&a.to_le_bytes());
+    bytes[8..12].copy_from_slice(&b.to_le_bytes());
+    bytes[12..16].copy_from_slice(&(event.x as u32).to_le_bytes());
+    bytes[16..20].copy_from_slice(&(event.y as u32).to_le_bytes());
+    bytes[20..24].copy_from_slice(&u32::from(event.buttons.bits()).to_le_bytes());
+    bytes[24..32].copy_from_slice(&event.time_ms.to_le_bytes());
+    bytes
+}
 
-    // And finally we can call the wasm!
-    hello.call(&mut store, ()).unwrap();
+fn encode_key_event(event: &KeyEvent) -> [u8; 24] {
+    let state: u32 = match event.state {
+        KeyState::Up => 0,
+        KeyState::Down if event.repeat => 2,
+        KeyState::Down => 1,
+        KeyState::SingleShot => 3,
+    };
+    let character = match event.key {
+        Some(DecodedKey::Unicode(c)) => u32::from(c),
+        _ => 0,
+    };
+    let mut bytes = [0; 24];
+    bytes[0..4].copy_from_slice(&keyboard::key_number(event.code).to_le_bytes());
+    bytes[4..8].copy_from_slice(&state.to_le_bytes());
+    bytes[8..12].copy_from_slice(&u32::from(event.modifiers.bits()).to_le_bytes());
+    bytes[12..16].copy_from_slice(&character.to_le_bytes());
+    bytes[16..24].copy_from_slice(&event.time_ms.to_le_bytes());
+    bytes
 }
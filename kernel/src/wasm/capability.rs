@@ -0,0 +1,130 @@
+//! What a process may do. A program only gets the host functions its capabilities allow,
+//! instantiating one that imports others fails with [`WasmError::Denied`](super::WasmError).
+//!
+//! Capabilities are written as a whitespace separated list:
+//!
+//! - `console`: `hello`, printing to the screen
+//! - `input`: `mouse_event` and `key_event`
+//! - `ipc`: channels, shared memory and handles, see [`crate::ipc`]
+//! - `port:NAME`: `port_bind` and `port_connect` for the port `NAME`, or any port for `port:*`.
+//!   Implies `ipc`.
+//! - `dir:PATH`: `file_read` for the files below `PATH` on the ramdisk, everything for `dir:/`
+//! - `spawn`: `spawn`, starting programs it may read with a subset of its own capabilities
+//!
+//! Arguments and environment variables are always available.
+use alloc::{string::String, vec::Vec};
+
+/// Every capability, what the `init` service gets if there's no manifest
+pub const ALL: &str = "console input ipc spawn dir:/ port:*";
+
+/// What a host function needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Console,
+    Input,
+    Ipc,
+    /// Any `port:`
+    Port,
+    /// Any `dir:`
+    Dir,
+    Spawn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CapabilityError {
+    /// Not a capability, or a `dir:` path with `..`
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    console: bool,
+    input: bool,
+    ipc: bool,
+    spawn: bool,
+    /// Directories whose files can be read, without leading or trailing `/`
+    dirs: Vec<String>,
+    /// Port names, `*` for all
+    ports: Vec<String>,
+}
+
+impl Capabilities {
+    /// No capabilities at all
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, CapabilityError> {
+        let mut caps = Self::none();
+        for word in spec.split_whitespace() {
+            match word.split_once(':') {
+                None if word == "console" => caps.console = true,
+                None if word == "input" => caps.input = true,
+                None if word == "ipc" => caps.ipc = true,
+                None if word == "spawn" => caps.spawn = true,
+                Some(("dir", path)) => {
+                    let path = normalize(path)
+                        .ok_or_else(|| CapabilityError::Unknown(String::from(word)))?;
+                    caps.dirs.push(String::from(path));
+                }
+                Some(("port", name)) if !name.is_empty() => {
+                    caps.ipc = true;
+                    caps.ports.push(String::from(name));
+                }
+                _ => return Err(CapabilityError::Unknown(String::from(word))),
+            }
+        }
+        Ok(caps)
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Console => self.console,
+            Capability::Input => self.input,
+            Capability::Ipc => self.ipc,
+            Capability::Port => !self.ports.is_empty(),
+            Capability::Dir => !self.dirs.is_empty(),
+            Capability::Spawn => self.spawn,
+        }
+    }
+
+    /// Whether the file at `path` can be read
+    pub fn can_read(&self, path: &str) -> bool {
+        let Some(path) = normalize(path) else {
+            return false;
+        };
+        self.dirs.iter().any(|dir| {
+            dir.is_empty()
+                || path
+                    .strip_prefix(dir.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Whether the port `name` can be bound or connected to
+    pub fn can_use_port(&self, name: &str) -> bool {
+        self.ports.iter().any(|port| port == "*" || port == name)
+    }
+
+    /// Whether these capabilities grant nothing `parent` doesn't, so `parent` may delegate them
+    pub fn is_subset_of(&self, parent: &Capabilities) -> bool {
+        let flags = [
+            (self.console, parent.console),
+            (self.input, parent.input),
+            (self.ipc, parent.ipc),
+            (self.spawn, parent.spawn),
+        ];
+        flags.iter().all(|&(child, parent)| !child || parent)
+            && self.dirs.iter().all(|dir| parent.can_read(dir))
+            && self.ports.iter().all(|port| {
+                parent.ports.iter().any(|p| p == "*") || (port != "*" && parent.can_use_port(port))
+            })
+    }
+}
+
+/// Strips the leading and trailing `/` from a path, `None` if it has `..` or empty components
+fn normalize(path: &str) -> Option<&str> {
+    let path = path.trim_matches('/');
+    let valid = path.is_empty() || path.split('/').all(|part| !part.is_empty() && part != "..");
+    valid.then_some(path)
+}
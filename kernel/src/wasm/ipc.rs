@@ -0,0 +1,321 @@
+//! Host functions for [message passing](crate::ipc) and [shared memory](crate::ipc::shm).
+//! They return a handle or 0 on success and a negative [`IpcError::code`] otherwise.
+use super::{read_memory, read_string, Capability, HostState};
+use crate::ipc::{self, HandleTable, IpcError, Object, SharedMemory};
+use crate::ipc::{MAX_MESSAGE_HANDLES, MAX_MESSAGE_LEN};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use wasmi::core::{HostError, Trap};
+use wasmi::{Caller, Extern, Func, Store};
+
+/// Raised by `wait` when no channel is ready, to suspend the program until one is. The run
+/// loop in [`super::run`] awaits it with [`Wait::wait`] and resumes the program with the result.
+#[derive(Debug)]
+pub(super) struct Wait(Vec<u32>);
+
+impl fmt::Display for Wait {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "waiting for channels {:?}", self.0)
+    }
+}
+
+impl HostError for Wait {}
+
+impl Wait {
+    pub(super) async fn wait(&self, handles: &mut HandleTable) -> i32 {
+        match handles.wait(&self.0).await {
+            Ok(index) => index as i32,
+            Err(err) => err.code(),
+        }
+    }
+}
+
+/// The host functions, see [`super::host_functions`]
+pub(super) fn functions(
+    store: &mut Store<HostState>,
+) -> Vec<(&'static str, Option<Capability>, Func)> {
+    let ipc = Some(Capability::Ipc);
+    Vec::from([
+        (
+            "channel_create",
+            ipc,
+            Func::wrap(&mut *store, channel_create),
+        ),
+        ("channel_send", ipc, Func::wrap(&mut *store, channel_send)),
+        ("channel_recv", ipc, Func::wrap(&mut *store, channel_recv)),
+        ("wait", ipc, Func::wrap(&mut *store, wait)),
+        ("handle_close", ipc, Func::wrap(&mut *store, handle_close)),
+        (
+            "handle_duplicate",
+            ipc,
+            Func::wrap(&mut *store, handle_duplicate),
+        ),
+        ("shm_create", ipc, Func::wrap(&mut *store, shm_create)),
+        ("shm_size", ipc, Func::wrap(&mut *store, shm_size)),
+        ("shm_read", ipc, Func::wrap(&mut *store, shm_read)),
+        ("shm_write", ipc, Func::wrap(&mut *store, shm_write)),
+        (
+            "port_bind",
+            Some(Capability::Port),
+            Func::wrap(&mut *store, port_bind),
+        ),
+        (
+            "port_connect",
+            Some(Capability::Port),
+            Func::wrap(&mut *store, port_connect),
+        ),
+    ])
+}
+
+fn code<T>(result: Result<T, IpcError>, ok: impl FnOnce(T) -> i32) -> i32 {
+    result.map_or_else(IpcError::code, ok)
+}
+
+/// Whether `len` bytes at `ptr` are inside the program's memory
+fn in_bounds(caller: &Caller<'_, HostState>, ptr: u32, len: usize) -> bool {
+    let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+        return false;
+    };
+    (ptr as usize)
+        .checked_add(len)
+        .is_some_and(|end| end <= memory.data(caller).len())
+}
+
+/// Copies bytes into the program's memory, which must be checked with [`in_bounds`] already
+fn write(caller: &mut Caller<'_, HostState>, ptr: u32, bytes: &[u8]) {
+    if let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) {
+        let _ = memory.write(caller, ptr as usize, bytes);
+    }
+}
+
+fn read_handles(
+    caller: &Caller<'_, HostState>,
+    ptr: u32,
+    count: u32,
+) -> Result<Vec<u32>, IpcError> {
+    if count as usize > MAX_MESSAGE_HANDLES {
+        return Err(IpcError::TooBig);
+    }
+    let bytes = read_memory(caller, ptr, count * 4).ok_or(IpcError::Invalid)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// `channel_create(ends_ptr) -> i32`: creates a channel and writes the handles of its two
+/// endpoints to `ends_ptr`, as two u32
+fn channel_create(mut caller: Caller<'_, HostState>, ends_ptr: u32) -> i32 {
+    if !in_bounds(&caller, ends_ptr, 8) {
+        return IpcError::Invalid.code();
+    }
+    let (a, b) = ipc::channel();
+    let handles = &mut caller.data_mut().handles;
+    let result =
+        handles
+            .insert(Object::Channel(a))
+            .and_then(|a| match handles.insert(Object::Channel(b)) {
+                Ok(b) => Ok([a, b]),
+                Err(err) => {
+                    let _ = handles.remove(a);
+                    Err(err)
+                }
+            });
+    match result {
+        Ok([a, b]) => {
+            write(&mut caller, ends_ptr, &a.to_le_bytes());
+            write(&mut caller, ends_ptr + 4, &b.to_le_bytes());
+            0
+        }
+        Err(err) => err.code(),
+    }
+}
+
+/// `channel_send(handle, ptr, len, handles_ptr, handles_len) -> i32`: sends `len` bytes at
+/// `ptr` and the `handles_len` handles (u32) at `handles_ptr`, which are gone from the process
+/// afterwards. Never waits, a full channel is an error.
+fn channel_send(
+    mut caller: Caller<'_, HostState>,
+    handle: u32,
+    ptr: u32,
+    len: u32,
+    handles_ptr: u32,
+    handles_len: u32,
+) -> i32 {
+    if len as usize > MAX_MESSAGE_LEN {
+        return IpcError::TooBig.code();
+    }
+    let Some(bytes) = read_memory(&caller, ptr, len) else {
+        return IpcError::Invalid.code();
+    };
+    let result = read_handles(&caller, handles_ptr, handles_len)
+        .and_then(|handles| caller.data_mut().handles.send(handle, bytes, &handles));
+    code(result, |()| 0)
+}
+
+/// `channel_recv(handle, ptr, len, handles_ptr, handles_len, actual_ptr) -> i32`: takes the
+/// next message without waiting. Its bytes go to `ptr`, the handles it carried to
+/// `handles_ptr`, and its length and number of handles to `actual_ptr` as two u32. If it
+/// doesn't fit into `len` bytes and `handles_len` handles, only the sizes are written and it
+/// stays queued.
+fn channel_recv(
+    mut caller: Caller<'_, HostState>,
+    handle: u32,
+    ptr: u32,
+    len: u32,
+    handles_ptr: u32,
+    handles_len: u32,
+    actual_ptr: u32,
+) -> i32 {
+    let fits = in_bounds(&caller, ptr, len as usize)
+        && in_bounds(&caller, handles_ptr, handles_len as usize * 4)
+        && in_bounds(&caller, actual_ptr, 8);
+    if !fits {
+        return IpcError::Invalid.code();
+    }
+    let result = caller
+        .data_mut()
+        .handles
+        .recv(handle, len as usize, handles_len as usize);
+    let (actual_len, actual_handles) = match &result {
+        Ok((bytes, handles)) => (bytes.len(), handles.len()),
+        Err(IpcError::TooSmall { len, handles }) => (*len, *handles),
+        Err(err) => return err.code(),
+    };
+    write(&mut caller, actual_ptr, &(actual_len as u32).to_le_bytes());
+    write(
+        &mut caller,
+        actual_ptr + 4,
+        &(actual_handles as u32).to_le_bytes(),
+    );
+    code(result, |(bytes, handles)| {
+        write(&mut caller, ptr, &bytes);
+        let handles: Vec<u8> = handles.iter().flat_map(|h| h.to_le_bytes()).collect();
+        write(&mut caller, handles_ptr, &handles);
+        0
+    })
+}
+
+/// `wait(handles_ptr, count) -> i32`: waits until one of the `count` channels (u32 handles)
+/// at `handles_ptr` has a message or its other end is gone, and returns its index
+fn wait(mut caller: Caller<'_, HostState>, handles_ptr: u32, count: u32) -> Result<i32, Trap> {
+    if count == 0 {
+        return Ok(IpcError::Invalid.code());
+    }
+    let handles = match read_handles(&caller, handles_ptr, count) {
+        Ok(handles) => handles,
+        Err(err) => return Ok(err.code()),
+    };
+    match caller.data_mut().handles.ready(&handles) {
+        Ok(Some(index)) => Ok(index as i32),
+        Ok(None) => Err(Wait(handles).into()),
+        Err(err) => Ok(err.code()),
+    }
+}
+
+/// `handle_close(handle) -> i32`: drops a handle and what it refers to
+fn handle_close(mut caller: Caller<'_, HostState>, handle: u32) -> i32 {
+    code(caller.data_mut().handles.remove(handle), |_| 0)
+}
+
+/// Reads a port name, which the process must have a `port:` capability for
+fn read_port_name(caller: &Caller<'_, HostState>, ptr: u32, len: u32) -> Result<String, IpcError> {
+    let name = read_string(caller, ptr, len, ipc::MAX_PORT_NAME_LEN as u32)?;
+    if !caller.data().caps.can_use_port(&name) {
+        return Err(IpcError::AccessDenied);
+    }
+    Ok(name)
+}
+
+/// `port_bind(name_ptr, name_len) -> i32`: publishes a port and returns the handle of the
+/// channel the connections to it arrive at, each as a message with one handle
+fn port_bind(mut caller: Caller<'_, HostState>, name_ptr: u32, name_len: u32) -> i32 {
+    let result = read_port_name(&caller, name_ptr, name_len)
+        .and_then(|name| ipc::bind(&name))
+        .and_then(|endpoint| caller.data_mut().handles.insert(Object::Channel(endpoint)));
+    code(result, |handle| handle as i32)
+}
+
+/// `port_connect(name_ptr, name_len) -> i32`: connects to a port and returns the handle of the
+/// new channel
+fn port_connect(mut caller: Caller<'_, HostState>, name_ptr: u32, name_len: u32) -> i32 {
+    let result = read_port_name(&caller, name_ptr, name_len)
+        .and_then(|name| ipc::connect(&name))
+        .and_then(|endpoint| caller.data_mut().handles.insert(Object::Channel(endpoint)));
+    code(result, |handle| handle as i32)
+}
+
+/// `handle_duplicate(handle, read_only) -> i32`: returns another handle to the same shared
+/// memory, which can't write to it if `read_only` isn't 0
+fn handle_duplicate(mut caller: Caller<'_, HostState>, handle: u32, read_only: u32) -> i32 {
+    let result = caller.data_mut().handles.duplicate(handle, read_only != 0);
+    code(result, |handle| handle as i32)
+}
+
+/// `shm_create(size) -> i32`: allocates `size` zeroed bytes of shared memory
+fn shm_create(mut caller: Caller<'_, HostState>, size: u32) -> i32 {
+    let result = SharedMemory::new(size as usize).and_then(|memory| {
+        caller
+            .data_mut()
+            .handles
+            .insert(Object::SharedMemory(memory))
+    });
+    code(result, |handle| handle as i32)
+}
+
+/// `shm_size(handle) -> i32`: the size of the shared memory in bytes
+fn shm_size(caller: Caller<'_, HostState>, handle: u32) -> i32 {
+    code(caller.data().handles.shared_memory(handle), |memory| {
+        memory.size() as i32
+    })
+}
+
+/// `shm_read(handle, offset, ptr, len) -> i32`: copies `len` bytes at `offset` in the shared
+/// memory to `ptr`
+fn shm_read(
+    mut caller: Caller<'_, HostState>,
+    handle: u32,
+    offset: u32,
+    ptr: u32,
+    len: u32,
+) -> i32 {
+    let result = caller.data().handles.shared_memory(handle).cloned();
+    let result = result.and_then(|memory| {
+        let data = program_memory(&mut caller, ptr, len)?;
+        memory.read(offset as usize, data)
+    });
+    code(result, |()| 0)
+}
+
+/// `shm_write(handle, offset, ptr, len) -> i32`: copies `len` bytes at `ptr` to `offset` in the
+/// shared memory
+fn shm_write(
+    mut caller: Caller<'_, HostState>,
+    handle: u32,
+    offset: u32,
+    ptr: u32,
+    len: u32,
+) -> i32 {
+    let result = caller.data().handles.shared_memory(handle).cloned();
+    let result = result.and_then(|memory| {
+        let data = program_memory(&mut caller, ptr, len)?;
+        memory.write(offset as usize, data)
+    });
+    code(result, |()| 0)
+}
+
+/// `len` bytes of the program's memory at `ptr`, to copy to or from directly
+fn program_memory<'a>(
+    caller: &'a mut Caller<'_, HostState>,
+    ptr: u32,
+    len: u32,
+) -> Result<&'a mut [u8], IpcError> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or(IpcError::Invalid)?;
+    let end = (ptr as usize).checked_add(len as usize);
+    let data = memory.data_mut(caller);
+    end.and_then(|end| data.get_mut(ptr as usize..end))
+        .ok_or(IpcError::Invalid)
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::task::simple_executor::SimpleExecutor;
+use kernel::task::sync::{broadcast, mpsc, oneshot, Mutex, Notify};
+use kernel::task::Task;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn mpsc_bounded_waits_for_room() {
+    let (tx, mut rx) = mpsc::channel(2);
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async move {
+        for i in 0..10 {
+            tx.send(i).await.unwrap();
+        }
+    }));
+    executor.spawn(Task::new(async move {
+        for i in 0..10 {
+            assert_eq!(rx.recv().await, Some(i));
+        }
+        assert_eq!(rx.recv().await, None);
+    }));
+    executor.run();
+}
+
+#[test_case]
+fn oneshot_reports_dropped_sender() {
+    let (tx, rx) = oneshot::channel::<u32>();
+    drop(tx);
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async move {
+        assert_eq!(rx.await, Err(oneshot::RecvError));
+    }));
+    executor.run();
+}
+
+#[test_case]
+fn broadcast_lagging_receiver() {
+    let (tx, mut rx) = broadcast::channel(2);
+    for i in 0..4 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(rx.try_recv(), Err(broadcast::TryRecvError::Lagged(2)));
+    assert_eq!(rx.try_recv(), Ok(2));
+    assert_eq!(rx.try_recv(), Ok(3));
+    assert_eq!(rx.try_recv(), Err(broadcast::TryRecvError::Empty));
+}
+
+#[test_case]
+fn mutex_and_notify() {
+    let counter = Arc::new(Mutex::new(0));
+    let notify = Arc::new(Notify::new());
+    let done = Arc::new(AtomicUsize::new(0));
+    let mut executor = SimpleExecutor::new();
+    for _ in 0..3 {
+        let (counter, notify, done) = (counter.clone(), notify.clone(), done.clone());
+        executor.spawn(Task::new(async move {
+            *counter.lock().await += 1;
+            if done.fetch_add(1, Ordering::SeqCst) == 2 {
+                notify.notify_one();
+            }
+        }));
+    }
+    let waiter = counter.clone();
+    executor.spawn(Task::new(async move {
+        notify.notified().await;
+        assert_eq!(*waiter.lock().await, 3);
+    }));
+    executor.run();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::wasm::{Capabilities, Capability, CapabilityError};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn parse_capabilities() {
+    let caps = Capabilities::parse("console  dir:/etc/ port:clock\n").unwrap();
+    assert!(caps.has(Capability::Console));
+    assert!(caps.has(Capability::Dir));
+    assert!(caps.has(Capability::Port));
+    // a port implies ipc
+    assert!(caps.has(Capability::Ipc));
+    assert!(!caps.has(Capability::Input));
+    assert!(!caps.has(Capability::Spawn));
+    assert_eq!(Capabilities::parse(""), Ok(Capabilities::none()));
+    assert!(matches!(
+        Capabilities::parse("console root"),
+        Err(CapabilityError::Unknown(word)) if word == "root"
+    ));
+    assert!(Capabilities::parse("port:").is_err());
+    assert!(Capabilities::parse("dir:/etc/../bin").is_err());
+}
+
+#[test_case]
+fn files_below_a_dir_can_be_read() {
+    let caps = Capabilities::parse("dir:/etc").unwrap();
+    assert!(caps.can_read("/etc/services"));
+    assert!(caps.can_read("etc/fonts/default.psf"));
+    assert!(!caps.can_read("/etcetera"));
+    assert!(!caps.can_read("/etc/../bin/shell.wasm"));
+    assert!(!caps.can_read("/bin/shell.wasm"));
+    let all = Capabilities::parse("dir:/").unwrap();
+    assert!(all.can_read("/bin/shell.wasm"));
+}
+
+#[test_case]
+fn ports_are_granted_by_name() {
+    let caps = Capabilities::parse("port:clock").unwrap();
+    assert!(caps.can_use_port("clock"));
+    assert!(!caps.can_use_port("display"));
+    assert!(Capabilities::parse("port:*")
+        .unwrap()
+        .can_use_port("display"));
+    assert!(!Capabilities::none().can_use_port("clock"));
+}
+
+#[test_case]
+fn children_get_a_subset() {
+    let parent = Capabilities::parse("console spawn dir:/bin port:clock").unwrap();
+    let subset = |spec| Capabilities::parse(spec).unwrap().is_subset_of(&parent);
+    assert!(subset(""));
+    assert!(subset("console dir:/bin/tools port:clock"));
+    // ports imply ipc, which the parent has through its own port
+    assert!(subset("ipc"));
+    assert!(!subset("input"));
+    assert!(!subset("dir:/"));
+    assert!(!subset("port:display"));
+    assert!(!subset("port:*"));
+    let all = Capabilities::parse(kernel::wasm::capability::ALL).unwrap();
+    assert!(parent.is_subset_of(&all));
+    assert!(!all.is_subset_of(&parent));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, string::String};
+use bootloader_api::{entry_point, BootInfo};
+use core::cell::RefCell;
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use kernel::task::executor::{self, Executor, SpawnError, Spawner};
+use kernel::task::Priority;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+/// Logs `tag` on every poll and wakes itself until it was polled `polls` times
+struct Yielding {
+    tag: char,
+    polls: usize,
+    log: Rc<RefCell<String>>,
+}
+
+impl Future for Yielding {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.log.borrow_mut().push(self.tag);
+        self.polls -= 1;
+        if self.polls == 0 {
+            return Poll::Ready(());
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Wakes itself several times in its first poll, then waits forever
+struct WakesTwice {
+    first: bool,
+}
+
+impl Future for WakesTwice {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.first {
+            self.first = false;
+            for _ in 0..3 {
+                cx.waker().wake_by_ref();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn priorities_get_their_budget_per_round() {
+    let log = Rc::new(RefCell::new(String::new()));
+    let spawner = Spawner::new(8);
+    let mut executor = Executor::new(spawner.clone());
+    // spawned lowest first, the order within a round only depends on the priority
+    for (tag, priority) in [
+        ('b', Priority::Background),
+        ('n', Priority::Interactive),
+        ('i', Priority::Input),
+    ] {
+        let log = log.clone();
+        spawner
+            .add_with_priority(
+                priority,
+                Yielding {
+                    tag,
+                    polls: 10,
+                    log,
+                },
+            )
+            .unwrap();
+    }
+    executor.run_until_idle();
+    let expected = [
+        "iiiiiiii", "nnnn", "bb", // round 1
+        "ii", "nnnn", "bb", // round 2
+        "nn", "bb", "bb", "bb",
+    ];
+    assert_eq!(*log.borrow(), expected.concat());
+    // finished tasks are dropped from the statistics
+    assert!(executor::stats()
+        .iter()
+        .all(|task| !task.name.ends_with("Yielding")));
+}
+
+#[test_case]
+fn repeated_wakeups_queue_a_task_once() {
+    let spawner = Spawner::new(8);
+    let mut executor = Executor::new(spawner.clone());
+    spawner
+        .add_with_priority(Priority::Input, WakesTwice { first: true })
+        .unwrap();
+    executor.run_until_idle();
+    let stats = executor::stats();
+    let task = stats
+        .iter()
+        .find(|task| task.name.ends_with("WakesTwice"))
+        .expect("pending task not in stats");
+    assert_eq!(task.priority, Priority::Input);
+    assert_eq!(task.polls, 2);
+}
+
+#[test_case]
+fn full_spawner_refuses_tasks() {
+    let spawner = Spawner::new(1);
+    assert_eq!(spawner.add(async {}), Ok(()));
+    assert_eq!(spawner.add(async {}), Err(SpawnError));
+    let mut executor = Executor::new(spawner.clone());
+    executor.run_until_idle();
+    assert_eq!(spawner.add(async {}), Ok(()));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
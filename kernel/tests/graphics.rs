@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::graphics::{qoi, Bitmap, Color, Painter, Rect, Rgba};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[test_case]
+fn line_is_clipped() {
+    let mut bitmap = Bitmap::new(4, 4);
+    Painter::new(&mut bitmap)
+        .clip(Rect::new(0, 0, 2, 4))
+        .line((-2, -2), (3, 3), Color::RED);
+    let red = Rgba::opaque(Color::RED);
+    assert_eq!(bitmap.get(0, 0), red);
+    assert_eq!(bitmap.get(1, 1), red);
+    assert_eq!(bitmap.get(2, 2), Rgba::TRANSPARENT);
+    assert_eq!(bitmap.get(3, 3), Rgba::TRANSPARENT);
+}
+
+#[test_case]
+fn blit_blends_alpha() {
+    let mut sprite = Bitmap::new(2, 1);
+    sprite.set(0, 0, Rgba::new(255, 255, 255, 255));
+    sprite.set(1, 0, Rgba::new(255, 255, 255, 0));
+    let mut bitmap = Bitmap::new(3, 1);
+    let mut painter = Painter::new(&mut bitmap);
+    painter.fill_rect(0, 0, 3, 1, Color::BLACK);
+    painter.blit(1, 0, &sprite);
+    assert_eq!(bitmap.get(1, 0), Rgba::new(255, 255, 255, 255));
+    assert_eq!(bitmap.get(2, 0), Rgba::opaque(Color::BLACK));
+}
+
+#[test_case]
+fn qoi_decodes_ops() {
+    #[rustfmt::skip]
+    let data = [
+        b'q', b'o', b'i', b'f', 0, 0, 0, 4, 0, 0, 0, 1, 4, 0,
+        0xfe, 10, 20, 30, // rgb
+        0xc1,             // run of 2
+        0x40 | 0b11_10_01, // diff +1 0 -1
+        0, 0, 0, 0, 0, 0, 0, 1,
+    ];
+    let bitmap = qoi::decode(&data).expect("valid image");
+    assert_eq!((bitmap.width(), bitmap.height()), (4, 1));
+    assert_eq!(
+        bitmap.pixels(),
+        &[
+            Rgba::new(10, 20, 30, 255),
+            Rgba::new(10, 20, 30, 255),
+            Rgba::new(10, 20, 30, 255),
+            Rgba::new(11, 20, 29, 255),
+        ]
+    );
+    assert_eq!(qoi::decode(&data[..16]), Err(qoi::QoiError::Truncated));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
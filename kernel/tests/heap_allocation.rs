@@ -12,33 +12,23 @@ use core::panic::PanicInfo;
 
 entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
 
-fn main(boot_info: &'static BootInfo) -> ! {
+/// How many boxes the `many_boxes` tests allocate and free again
+const BOX_COUNT: usize = 100 * 1024;
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
     use kernel::allocator;
-    use kernel::framebuffer::{FrameBufferWriter, FRAMEBUFFER};
     use kernel::memory::{self, BootInfoFrameAllocator};
     use x86_64::VirtAddr;
 
     kernel::init();
-    FRAMEBUFFER.init_once(|| {
-        let frame = boot_info.framebuffer.as_mut();
-        let info = match frame {
-            Some(ref v) => v.info(),
-            None => panic!("BOOTLOADER NOT CONFIGURED TO SUPPORT FRAMEBUFFER"),
-        };
-        let buffer = match frame {
-            Some(v) => v.buffer_mut(),
-            None => panic!("BOOTLOADER NOT CONFIGURED TO SUPPORT FRAMEBUFFER"),
-        };
-        spinning_top::Spinlock::new(FrameBufferWriter::new(buffer, info))
-    });
-
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
 
     test_main();
-    loop {}
+    kernel::hlt_loop();
 }
 
 #[test_case]
@@ -61,8 +51,7 @@ fn large_vec() {
 
 #[test_case]
 fn many_boxes() {
-    use kernel::allocator::HEAP_SIZE;
-    for i in 0..HEAP_SIZE {
+    for i in 0..BOX_COUNT {
         let x = Box::new(i);
         assert_eq!(*x, i);
     }
@@ -70,16 +59,34 @@ fn many_boxes() {
 
 #[test_case]
 fn many_boxes_long_lived() {
-    use yos::allocator::HEAP_SIZE;
     let long_lived = Box::new(1); // new
-    for i in 0..HEAP_SIZE {
+    for i in 0..BOX_COUNT {
         let x = Box::new(i);
         assert_eq!(*x, i);
     }
     assert_eq!(*long_lived, 1); // new
 }
 
+#[test_case]
+fn demand_paged_reservation() {
+    use kernel::memory::vma;
+    use x86_64::structures::paging::PageTableFlags;
+
+    let size = 1024 * 1024;
+    let start = vma::reserve("test", size, PageTableFlags::WRITABLE).expect("reserve failed");
+    let words = start.as_mut_ptr::<u64>();
+    // only the touched pages get mapped
+    for i in (0..size as usize / 8).step_by(4096 / 8) {
+        unsafe {
+            assert_eq!(words.add(i).read_volatile(), 0);
+            words.add(i).write_volatile(i as u64);
+            assert_eq!(words.add(i).read_volatile(), i as u64);
+        }
+    }
+    assert!(vma::remove(start).is_some());
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    yos::test_panic_handler(info)
+    kernel::test_panic_handler(info)
 }
@@ -0,0 +1,195 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::ipc::{self, HandleTable, IpcError, Message, Object, SharedMemory};
+use kernel::task::simple_executor::SimpleExecutor;
+use kernel::task::Task;
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+/// A table with both ends of a new channel
+fn table_with_channel() -> (HandleTable, u32, u32) {
+    let (a, b) = ipc::channel();
+    let mut table = HandleTable::new();
+    let a = table.insert(Object::Channel(a)).unwrap();
+    let b = table.insert(Object::Channel(b)).unwrap();
+    (table, a, b)
+}
+
+#[test_case]
+fn messages_arrive_in_order() {
+    let (mut table, a, b) = table_with_channel();
+    table.send(a, Vec::from(*b"one"), &[]).unwrap();
+    table.send(a, Vec::from(*b"two"), &[]).unwrap();
+    assert_eq!(table.ready(&[a, b]), Ok(Some(1)));
+    assert_eq!(table.recv(b, 16, 0).unwrap().0, b"one");
+    assert_eq!(table.recv(b, 16, 0).unwrap().0, b"two");
+    assert_eq!(table.recv(b, 16, 0).unwrap_err(), IpcError::Empty);
+    assert_eq!(table.ready(&[a, b]), Ok(None));
+}
+
+#[test_case]
+fn too_small_buffers_leave_the_message_queued() {
+    let (mut table, a, b) = table_with_channel();
+    table.send(a, Vec::from(*b"hello"), &[]).unwrap();
+    assert_eq!(
+        table.recv(b, 4, 0).unwrap_err(),
+        IpcError::TooSmall { len: 5, handles: 0 }
+    );
+    assert_eq!(table.recv(b, 5, 0).unwrap().0, b"hello");
+}
+
+#[test_case]
+fn handles_move_with_messages() {
+    let (mut sender, a, b) = table_with_channel();
+    let (mut receiver, c, d) = table_with_channel();
+    // the receiver gets one end of the sender's channel, through its own channel
+    let Ok(Object::Channel(to_receiver)) = receiver.remove(d) else {
+        panic!("no channel");
+    };
+    let d = sender.insert(Object::Channel(to_receiver)).unwrap();
+    assert_eq!(sender.send(d, Vec::new(), &[d]), Err(IpcError::Invalid));
+    assert_eq!(sender.send(d, Vec::new(), &[b, b]), Err(IpcError::Invalid));
+    sender.send(d, Vec::from(*b"take this"), &[b]).unwrap();
+    assert!(sender.channel(b).is_err());
+
+    let (bytes, handles) = receiver.recv(c, 16, 1).unwrap();
+    assert_eq!(bytes, b"take this");
+    sender.send(a, Vec::from(*b"hi"), &[]).unwrap();
+    assert_eq!(receiver.recv(handles[0], 16, 0).unwrap().0, b"hi");
+}
+
+#[test_case]
+fn channel_ends_cant_be_sent_over_their_channel() {
+    let (mut table, a, b) = table_with_channel();
+    assert_eq!(table.send(a, Vec::new(), &[b]), Err(IpcError::Invalid));
+    assert_eq!(table.send(b, Vec::new(), &[a]), Err(IpcError::Invalid));
+    assert!(table.channel(b).is_ok());
+    assert_eq!(table.recv(b, 0, 0).unwrap_err(), IpcError::Empty);
+}
+
+#[test_case]
+fn failed_send_keeps_the_handles() {
+    let (mut table, a, b) = table_with_channel();
+    let (c, _d) = ipc::channel();
+    let c = table.insert(Object::Channel(c)).unwrap();
+    table.remove(b).unwrap();
+    assert_eq!(table.send(a, Vec::new(), &[c]), Err(IpcError::Closed));
+    assert!(table.channel(c).is_ok());
+    assert_eq!(table.recv(a, 0, 0).unwrap_err(), IpcError::Closed);
+}
+
+#[test_case]
+fn ports_hand_out_connections() {
+    let mut server = ipc::bind("test.echo").unwrap();
+    assert!(matches!(
+        ipc::bind("test.echo"),
+        Err(IpcError::AddressInUse)
+    ));
+    let client = ipc::connect("test.echo").unwrap();
+    let Message { mut handles, .. } = server.try_recv().unwrap();
+    let Some(Object::Channel(mut connection)) = handles.pop() else {
+        panic!("no connection");
+    };
+    client
+        .send(Message {
+            bytes: Vec::from(*b"ping"),
+            handles: Vec::new(),
+        })
+        .unwrap();
+    assert_eq!(connection.try_recv().unwrap().bytes, b"ping");
+
+    drop(server);
+    assert!(matches!(ipc::connect("test.echo"), Err(IpcError::NotFound)));
+    assert!(ipc::bind("test.echo").is_ok());
+}
+
+#[test_case]
+fn wait_for_a_message() {
+    let (a, mut b) = ipc::channel();
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async move {
+        assert_eq!(b.recv().await.unwrap().bytes, b"late");
+        assert_eq!(b.recv().await.err(), Some(IpcError::Closed));
+    }));
+    executor.spawn(Task::new(async move {
+        a.send(Message {
+            bytes: Vec::from(*b"late"),
+            handles: Vec::new(),
+        })
+        .unwrap();
+    }));
+    executor.run();
+}
+
+#[test_case]
+fn shared_memory_is_shared() {
+    let (mut owner, a, b) = table_with_channel();
+    let mut viewer = HandleTable::new();
+    let Ok(Object::Channel(b)) = owner.remove(b) else {
+        panic!("no channel");
+    };
+    let b = viewer.insert(Object::Channel(b)).unwrap();
+
+    let memory = SharedMemory::new(64).unwrap();
+    let handle = owner.insert(Object::SharedMemory(memory)).unwrap();
+    let read_only = owner.duplicate(handle, true).unwrap();
+    assert_eq!(owner.duplicate(a, false), Err(IpcError::WrongType));
+    owner.send(a, Vec::new(), &[read_only]).unwrap();
+    let (_, handles) = viewer.recv(b, 0, 1).unwrap();
+
+    let shared = viewer.shared_memory(handles[0]).unwrap();
+    assert_eq!(shared.views(), 2);
+    assert!(!shared.is_writable());
+    assert_eq!(shared.write(0, b"no"), Err(IpcError::AccessDenied));
+    let writer = owner.shared_memory(handle).unwrap();
+    assert_eq!(writer.write(60, b"pixels"), Err(IpcError::Invalid));
+    writer.write(58, b"pixels").unwrap();
+    let mut pixels = [0; 6];
+    shared.read(58, &mut pixels).unwrap();
+    assert_eq!(&pixels, b"pixels");
+
+    // the buffer lives as long as any handle to it
+    owner.remove(handle).unwrap();
+    let shared = viewer.shared_memory(handles[0]).unwrap();
+    assert_eq!(shared.views(), 1);
+    assert_eq!(shared.size(), 64);
+}
+
+#[test_case]
+fn shared_memory_size_is_limited() {
+    assert!(matches!(SharedMemory::new(0), Err(IpcError::Invalid)));
+    assert!(matches!(
+        SharedMemory::new(ipc::shm::MAX_SIZE + 1),
+        Err(IpcError::Invalid)
+    ));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::keyboard::{Keymap, KeymapError, Layout};
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+
+entry_point!(main, config = &kernel::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    kernel::init();
+    let phys_mem_offset = VirtAddr::new(*boot_info.physical_memory_offset.as_ref().unwrap());
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+fn modifiers(shift: bool, capslock: bool, altgr: bool) -> Modifiers {
+    Modifiers {
+        lshift: shift,
+        rshift: false,
+        lctrl: false,
+        rctrl: false,
+        numlock: true,
+        capslock,
+        lalt: false,
+        ralt: altgr,
+        rctrl2: false,
+    }
+}
+
+const KEYMAP: &str = "
+# swaps q and a, with extras
+name test
+base uk
+Q     a  A
+A     q  Q
+Key2  2  @  U+00B2
+";
+
+#[test_case]
+fn keymap_maps_listed_keys() {
+    let keymap = Keymap::parse("unnamed", KEYMAP).unwrap();
+    assert_eq!(keymap.name(), "test");
+    let map = |key, modifiers| keymap.map_keycode(key, &modifiers, HandleControl::Ignore);
+    assert_eq!(
+        map(KeyCode::Q, modifiers(false, false, false)),
+        DecodedKey::Unicode('a')
+    );
+    assert_eq!(
+        map(KeyCode::A, modifiers(false, true, false)),
+        DecodedKey::Unicode('Q')
+    );
+    // caps lock doesn't shift digits
+    assert_eq!(
+        map(KeyCode::Key2, modifiers(false, true, false)),
+        DecodedKey::Unicode('2')
+    );
+    assert_eq!(
+        map(KeyCode::Key2, modifiers(false, false, true)),
+        DecodedKey::Unicode('²')
+    );
+    // unlisted keys come from the base layout
+    assert_eq!(
+        map(KeyCode::W, modifiers(false, false, false)),
+        Layout::Uk105.map_keycode(
+            KeyCode::W,
+            &modifiers(false, false, false),
+            HandleControl::Ignore
+        )
+    );
+}
+
+#[test_case]
+fn keymap_reports_bad_lines() {
+    assert_eq!(
+        Keymap::parse("bad", "base qwertz").err(),
+        Some(KeymapError::Parse {
+            line: 1,
+            reason: "unknown base layout"
+        })
+    );
+    assert_eq!(
+        Keymap::parse("bad", "\nQ ab").err(),
+        Some(KeymapError::Parse {
+            line: 2,
+            reason: "bad character"
+        })
+    );
+    // a typo would otherwise never match a key
+    assert_eq!(
+        Keymap::parse("bad", "Q a A\nKey22 2 @").err(),
+        Some(KeymapError::Parse {
+            line: 2,
+            reason: "unknown key"
+        })
+    );
+}
+
+#[test_case]
+fn layouts_by_name() {
+    assert_eq!(
+        Layout::by_name("DE").map(|layout| layout.name().into()),
+        Some(alloc::string::String::from("de"))
+    );
+    assert!(Layout::by_name("qwertz").is_none());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
@@ -0,0 +1,226 @@
+//! Boots the kernel headless and drives its shell over the serial port, for end to end tests.
+//!
+//! ```no_run
+//! let mut kernel = yos::harness::Kernel::boot().unwrap();
+//! assert_eq!(kernel.run("echo hello").unwrap(), "hello\n");
+//! kernel.send_line("poweroff").unwrap();
+//! assert_eq!(kernel.wait_exit().unwrap(), Some(yos::harness::EXIT_SUCCESS));
+//! ```
+use crate::image::Image;
+use crate::Options;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What the shell prints when it waits for a command. The kernel stops logging to the serial
+/// port once the first command arrives, so command output isn't interleaved with log lines.
+pub const PROMPT: &str = "yos> ";
+/// Code the kernel passes to `exit_qemu` on success, see [`Kernel::wait_exit`]
+pub const EXIT_SUCCESS: u32 = 0x10;
+pub const EXIT_FAILED: u32 = 0x11;
+/// How long [`Kernel::expect`] and [`Kernel::wait_exit`] wait by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum HarnessError {
+    Io(io::Error),
+    /// The expected text didn't show up in time, `output` is what did
+    Timeout {
+        expected: String,
+        output: String,
+    },
+    /// QEMU exited while output was expected
+    Exited {
+        expected: String,
+        output: String,
+    },
+}
+
+impl fmt::Display for HarnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "QEMU i/o failed: {err}"),
+            Self::Timeout { expected, output } => {
+                write!(f, "timed out waiting for {expected:?}, got:\n{output}")
+            }
+            Self::Exited { expected, output } => {
+                write!(f, "QEMU exited waiting for {expected:?}, got:\n{output}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HarnessError {}
+
+impl From<io::Error> for HarnessError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Everything the kernel wrote to the serial port so far
+#[derive(Default)]
+struct Output {
+    bytes: Mutex<(Vec<u8>, bool)>,
+    /// Signalled when bytes arrive or QEMU closes the port
+    changed: Condvar,
+}
+
+/// A kernel running in QEMU. QEMU is killed when this is dropped.
+pub struct Kernel {
+    child: Child,
+    stdin: ChildStdin,
+    output: Arc<Output>,
+    /// How much of the output [`Kernel::expect`] consumed
+    consumed: usize,
+    timeout: Duration,
+    /// Deleted after QEMU was killed
+    _image: Image,
+}
+
+impl Kernel {
+    /// Boots the BIOS image and waits for the shell's first prompt
+    pub fn boot() -> Result<Kernel, HarnessError> {
+        Self::boot_with(&Options::default())
+    }
+
+    /// Boots with more options, always headless
+    pub fn boot_with(options: &Options) -> Result<Kernel, HarnessError> {
+        let options = Options {
+            headless: true,
+            ..options.clone()
+        };
+        let image = options.image()?;
+        let mut child = options
+            .command(image.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let output = Arc::new(Output::default());
+        let writer = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            loop {
+                let read = stdout.read(&mut chunk).unwrap_or(0);
+                let mut bytes = writer.bytes.lock().unwrap();
+                match read {
+                    0 => bytes.1 = true,
+                    read => bytes.0.extend_from_slice(&chunk[..read]),
+                }
+                writer.changed.notify_all();
+                if read == 0 {
+                    break;
+                }
+            }
+        });
+        let mut kernel = Kernel {
+            child,
+            stdin,
+            output,
+            consumed: 0,
+            timeout: DEFAULT_TIMEOUT,
+            _image: image,
+        };
+        kernel.expect(PROMPT)?;
+        Ok(kernel)
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Writes to the serial port as if typed
+    pub fn send(&mut self, text: &str) -> Result<(), HarnessError> {
+        self.stdin.write_all(text.as_bytes())?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    pub fn send_line(&mut self, line: &str) -> Result<(), HarnessError> {
+        self.send(&format!("{line}\r"))
+    }
+
+    /// Waits for `text` in the output that wasn't consumed yet. Returns the output up to and
+    /// including it, which is consumed.
+    pub fn expect(&mut self, text: &str) -> Result<String, HarnessError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut bytes = self.output.bytes.lock().unwrap();
+        loop {
+            let unread = &bytes.0[self.consumed..];
+            if let Some(pos) = find(unread, text.as_bytes()) {
+                let end = pos + text.len();
+                let found = String::from_utf8_lossy(&unread[..end]).into_owned();
+                self.consumed += end;
+                return Ok(found);
+            }
+            let output = String::from_utf8_lossy(unread).into_owned();
+            let expected = text.to_owned();
+            if bytes.1 {
+                return Err(HarnessError::Exited { expected, output });
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(HarnessError::Timeout { expected, output });
+            }
+            bytes = self
+                .output
+                .changed
+                .wait_timeout(bytes, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Runs a shell command and returns what it printed before the next prompt
+    pub fn run(&mut self, command: &str) -> Result<String, HarnessError> {
+        self.send_line(command)?;
+        // the shell echoes the line
+        self.expect(&format!("{command}\r\n"))?;
+        let output = self.expect(PROMPT)?;
+        Ok(output[..output.len() - PROMPT.len()].to_owned())
+    }
+
+    /// Everything the kernel wrote so far
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output.bytes.lock().unwrap().0).into_owned()
+    }
+
+    /// Waits for QEMU to exit and returns the code the kernel passed to `exit_qemu`, `None` if
+    /// it exited some other way
+    pub fn wait_exit(&mut self) -> Result<Option<u32>, HarnessError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(crate::debug_exit_code(status));
+            }
+            if Instant::now() >= deadline {
+                return Err(HarnessError::Timeout {
+                    expected: "QEMU to exit".into(),
+                    output: self.output(),
+                });
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Kernel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
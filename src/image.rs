@@ -0,0 +1,94 @@
+//! Disk images with extra files on the ramdisk, built when the runner starts
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The kernel the build script put into the prebuilt images
+const KERNEL: &str = env!("KERNEL_PATH");
+/// Their ramdisk, with the kernel's symbols and keymaps
+const RAMDISK: &str = env!("RAMDISK_PATH");
+
+/// A disk image to boot. Built ones live in a directory of their own, which is deleted when
+/// the image is dropped.
+#[derive(Debug)]
+pub struct Image {
+    path: PathBuf,
+    temp_dir: Option<PathBuf>,
+}
+
+impl Image {
+    /// One of the images the build script made, which is kept
+    pub fn prebuilt(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            temp_dir: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.temp_dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Builds a disk image whose ramdisk has the files of `initrd` and the kernel command line on
+/// top of the usual ones
+pub fn build(initrd: Option<&Path>, cmdline: Option<&str>, uefi: bool) -> io::Result<Image> {
+    // tests boot several kernels from one process at once
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    let out_dir = std::env::temp_dir().join(format!("yos-{}-{id}", std::process::id()));
+    std::fs::create_dir_all(&out_dir)?;
+    // cleans up if anything below fails
+    let mut image = Image {
+        path: PathBuf::new(),
+        temp_dir: Some(out_dir.clone()),
+    };
+
+    let ramdisk_path = out_dir.join("ramdisk.tar");
+    let mut ramdisk = tar::Builder::new(File::create(&ramdisk_path)?);
+    let mut base = tar::Archive::new(File::open(RAMDISK)?);
+    for entry in base.entries()? {
+        let mut entry = entry?;
+        let mut header = entry.header().clone();
+        let path = entry.path()?.into_owned();
+        ramdisk.append_data(&mut header, path, &mut entry)?;
+    }
+    if let Some(dir) = initrd {
+        ramdisk.append_dir_all("", dir)?;
+    }
+    if let Some(cmdline) = cmdline {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(cmdline.len() as u64);
+        header.set_mode(0o644);
+        ramdisk.append_data(&mut header, "cmdline", cmdline.as_bytes())?;
+    }
+    ramdisk.finish()?;
+
+    let kernel = Path::new(KERNEL);
+    let created = match uefi {
+        true => {
+            image.path = out_dir.join("uefi.img");
+            bootloader::UefiBoot::new(kernel)
+                .set_ramdisk(&ramdisk_path)
+                .create_disk_image(&image.path)
+        }
+        false => {
+            image.path = out_dir.join("bios.img");
+            bootloader::BiosBoot::new(kernel)
+                .set_ramdisk(&ramdisk_path)
+                .create_disk_image(&image.path)
+        }
+    };
+    created.map_err(io::Error::other)?;
+    Ok(image)
+}
@@ -0,0 +1,43 @@
+//! Runs the kernel in QEMU, interactively with `cargo run` or from tests with [`harness`].
+
+pub mod harness;
+pub mod image;
+pub mod options;
+
+pub use options::Options;
+
+use std::process::ExitStatus;
+
+/// Disk image booted with BIOS
+pub const BIOS_IMAGE: &str = env!("BIOS_PATH");
+/// Disk image booted with UEFI
+pub const UEFI_IMAGE: &str = env!("UEFI_PATH");
+
+/// The code the kernel passed to `exit_qemu`, `None` if QEMU exited some other way
+pub fn debug_exit_code(status: ExitStatus) -> Option<u32> {
+    // `isa-debug-exit` makes QEMU exit with `(code << 1) | 1`. Code 0 would be 1, which is
+    // also how QEMU reports its own errors, so the kernel never uses it.
+    status
+        .code()
+        .filter(|&code| code > 1 && code & 1 == 1)
+        .map(|code| code as u32 >> 1)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn debug_exit_codes() {
+        assert_eq!(debug_exit_code(exited(0x21)), Some(harness::EXIT_SUCCESS));
+        assert_eq!(debug_exit_code(exited(0x23)), Some(harness::EXIT_FAILED));
+        // QEMU itself failed, e.g. to open a disk
+        assert_eq!(debug_exit_code(exited(1)), None);
+        assert_eq!(debug_exit_code(exited(0)), None);
+    }
+}
@@ -1,30 +1,33 @@
-fn main() {
-    // read env variables that were set in build script
-    let uefi_path = env!("UEFI_PATH");
-    let bios_path = env!("BIOS_PATH");
-
-    // choose whether to start the UEFI or BIOS image
-    let uefi = false;
+use yos::options::{Options, USAGE};
 
-    let mut cmd = std::process::Command::new("qemu-system-x86_64");
-    if uefi {
-        //cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-        cmd.arg("-drive")
-            .arg(format!("format=raw,file={uefi_path}"));
-    } else {
-        cmd.arg("-drive")
-            .arg(format!("format=raw,file={bios_path}"));
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) if options.help => {
+            println!("{USAGE}");
+            return;
+        }
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let image = options.image().unwrap_or_else(|err| {
+        eprintln!("can't build the disk image: {err}");
+        std::process::exit(1);
+    });
+    if options.gdb {
+        eprintln!("waiting for GDB on localhost:1234");
     }
-    cmd.args([
-        "-device",
-        "isa-debug-exit,iobase=0xf4,iosize=0x04",
-        "-serial",
-        "stdio",
-        "-device",
-        "virtio-mouse",
-        "-device",
-        "virtio-keyboard",
-    ]);
-    let mut child = cmd.spawn().unwrap();
-    child.wait().unwrap();
+    let status = options
+        .command(image.path())
+        .status()
+        .expect("failed to start QEMU");
+    let code = yos::debug_exit_code(status)
+        .map(|code| code as i32)
+        .or(status.code())
+        .unwrap_or(1);
+    // exiting skips destructors
+    drop(image);
+    std::process::exit(code);
 }
@@ -0,0 +1,158 @@
+//! What to run QEMU with, from the command line of the runner
+
+use crate::image::Image;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const USAGE: &str = "\
+usage: yos [OPTIONS] [-- QEMU_ARGS...]
+
+options:
+  --uefi           boot with UEFI instead of BIOS
+  --memory SIZE    guest memory, like 512M or 2G
+  --smp N          number of CPUs
+  --gdb            wait for GDB on localhost:1234 before starting
+  --disk IMAGE     attach a raw disk image as virtio drive, may be repeated
+  --headless       no display, talk to the kernel over the serial port on stdio
+  --initrd DIR     add the files of DIR to the ramdisk
+  --append OPTIONS kernel command line, like \"keymap=de init=/app.wasm\"
+  -h, --help       show this
+
+Arguments after `--` are passed to QEMU. The runner exits with the code the kernel passed
+to `exit_qemu`, or QEMU's own exit status.";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub uefi: bool,
+    /// Passed to `-m`
+    pub memory: Option<String>,
+    pub smp: Option<u32>,
+    pub gdb: bool,
+    pub disks: Vec<PathBuf>,
+    pub headless: bool,
+    /// Directory whose files are added to the ramdisk
+    pub initrd: Option<PathBuf>,
+    /// Kernel command line, put on the ramdisk as `cmdline`
+    pub cmdline: Option<String>,
+    pub qemu_args: Vec<String>,
+    pub help: bool,
+}
+
+impl Options {
+    /// Parses the arguments, without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+            match arg.as_str() {
+                "--uefi" => options.uefi = true,
+                "--bios" => options.uefi = false,
+                "--memory" => options.memory = Some(value("--memory")?),
+                "--smp" => {
+                    let smp = value("--smp")?;
+                    let smp = smp.parse().map_err(|_| format!("bad --smp: {smp}"))?;
+                    options.smp = Some(smp);
+                }
+                "--gdb" => options.gdb = true,
+                "--disk" => options.disks.push(value("--disk")?.into()),
+                "--headless" => options.headless = true,
+                "--initrd" => options.initrd = Some(value("--initrd")?.into()),
+                "--append" => options.cmdline = Some(value("--append")?),
+                "-h" | "--help" => options.help = true,
+                "--" => {
+                    options.qemu_args.extend(args);
+                    break;
+                }
+                other => return Err(format!("unknown argument: {other}")),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The disk image to boot, which is built first if there are extra ramdisk files
+    pub fn image(&self) -> io::Result<Image> {
+        if self.initrd.is_some() || self.cmdline.is_some() {
+            return crate::image::build(self.initrd.as_deref(), self.cmdline.as_deref(), self.uefi);
+        }
+        match self.uefi {
+            true => Ok(Image::prebuilt(crate::UEFI_IMAGE)),
+            false => Ok(Image::prebuilt(crate::BIOS_IMAGE)),
+        }
+    }
+
+    /// A QEMU command booting `image`, with COM1 on stdio and the `isa-debug-exit` device the
+    /// kernel exits through
+    pub fn command(&self, image: &Path) -> Command {
+        let mut cmd = Command::new("qemu-system-x86_64");
+        cmd.arg("-drive")
+            .arg(format!("format=raw,file={}", image.display()));
+        if self.uefi {
+            cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
+        }
+        cmd.args([
+            "-device",
+            "isa-debug-exit,iobase=0xf4,iosize=0x04",
+            "-serial",
+            "stdio",
+            "-device",
+            "virtio-mouse",
+            "-device",
+            "virtio-keyboard",
+        ]);
+        if let Some(memory) = &self.memory {
+            cmd.arg("-m").arg(memory);
+        }
+        if let Some(smp) = self.smp {
+            cmd.arg("-smp").arg(smp.to_string());
+        }
+        if self.gdb {
+            cmd.args(["-s", "-S"]);
+        }
+        for disk in &self.disks {
+            cmd.arg("-drive")
+                .arg(format!("format=raw,if=virtio,file={}", disk.display()));
+        }
+        if self.headless {
+            cmd.args(["-display", "none"]);
+        }
+        cmd.args(&self.qemu_args);
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&[
+            "--uefi", "--memory", "1G", "--smp", "4", "--disk", "a.img", "--disk", "b.img",
+            "--append", "heap=64M", "--", "-d", "int",
+        ])
+        .unwrap();
+        assert!(options.uefi);
+        assert_eq!(options.memory.as_deref(), Some("1G"));
+        assert_eq!(options.smp, Some(4));
+        assert_eq!(
+            options.disks,
+            [PathBuf::from("a.img"), PathBuf::from("b.img")]
+        );
+        assert_eq!(options.cmdline.as_deref(), Some("heap=64M"));
+        assert_eq!(options.qemu_args, ["-d", "int"]);
+        assert!(!options.gdb && !options.headless);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--smp", "many"]).is_err());
+        assert!(parse(&["--memory"]).is_err());
+        assert!(parse(&["--fast"]).is_err());
+    }
+}
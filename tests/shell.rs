@@ -0,0 +1,76 @@
+//! Boots the kernel in QEMU and drives the serial shell
+
+use yos::harness::{Kernel, EXIT_SUCCESS};
+use yos::Options;
+
+#[test]
+fn echo() {
+    let mut kernel = Kernel::boot().unwrap();
+    assert_eq!(kernel.run("echo hello world").unwrap(), "hello world\n");
+}
+
+#[test]
+fn unknown_command() {
+    let mut kernel = Kernel::boot().unwrap();
+    let output = kernel.run("frobnicate").unwrap();
+    assert!(output.contains("unknown command"), "{output}");
+}
+
+#[test]
+fn backspace_edits_the_line() {
+    let mut kernel = Kernel::boot().unwrap();
+    kernel.send("echo typp\x7fo\r").unwrap();
+    kernel.expect("\r\n").unwrap();
+    let output = kernel.expect(yos::harness::PROMPT).unwrap();
+    assert_eq!(output, format!("typo\n{}", yos::harness::PROMPT));
+}
+
+#[test]
+fn switch_keyboard_layout() {
+    let mut kernel = Kernel::boot().unwrap();
+    assert_eq!(kernel.run("layout").unwrap(), "us\n");
+    kernel.run("layout workman").unwrap();
+    assert_eq!(kernel.run("layout").unwrap(), "workman\n");
+}
+
+#[test]
+fn poweroff_exits_qemu() {
+    let mut kernel = Kernel::boot().unwrap();
+    kernel.send_line("poweroff").unwrap();
+    kernel.expect("bye").unwrap();
+    assert_eq!(kernel.wait_exit().unwrap(), Some(EXIT_SUCCESS));
+}
+
+#[test]
+fn keymap_from_command_line() {
+    let options = Options {
+        cmdline: Some("keymap=dvorak".into()),
+        ..Options::default()
+    };
+    let mut kernel = Kernel::boot_with(&options).unwrap();
+    assert_eq!(kernel.run("layout").unwrap(), "dvorak\n");
+}
+
+#[test]
+fn services_from_manifest() {
+    let dir = std::env::temp_dir().join(format!("yos-services-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("etc")).unwrap();
+    let manifest = "[hello]\nprogram = builtin:example\ncaps = console\nrestart = never\n\n\
+                    [denied]\nprogram = builtin:example\nrestart = never\n\n\
+                    [missing]\nprogram = /bin/missing.wasm\n";
+    std::fs::write(dir.join("etc/services"), manifest).unwrap();
+    let options = Options {
+        initrd: Some(dir.clone()),
+        ..Options::default()
+    };
+    let mut kernel = Kernel::boot_with(&options).unwrap();
+    let output = kernel.run("services").unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    let state = |name: &str| {
+        let line = output.lines().find(|line| line.ends_with(name));
+        line.and_then(|line| line.split_whitespace().next())
+    };
+    assert_eq!(state("hello"), Some("exited"), "{output}");
+    assert_eq!(state("denied"), Some("failed"), "{output}");
+    assert_eq!(state("missing"), Some("failed"), "{output}");
+}